@@ -2,7 +2,10 @@
 
 extern crate test;
 
+use std::collections::BTreeMap;
+
 use rustytracer::camera::Camera;
+use rustytracer::camera::ProjectiveCamera;
 use rustytracer::image::Image;
 use rustytracer::image::Coloru8;
 use rustytracer::image::Color;
@@ -13,8 +16,10 @@ use rustytracer::renderer::RenderTarget;
 use rustytracer::utils;
 use rustytracer::materials::Lambertian;
 use rustytracer::hittables::Sphere;
+use rustytracer::hittables::Triangle;
 use rustytracer::hittables::Hittable;
 use rustytracer::ray::Ray;
+use rustytracer::sampler::IndependentSampler;
 
 use cgmath::Vector3;
 
@@ -33,8 +38,9 @@ mod benches {
         let aspect = 16.0 / 9.0;
         let u = 0.25;
         let v = 0.25;
-        let camera = Camera::new(origin, target, up, vfov, aspect, aperature);
-        b.iter(|| black_box(camera.get_ray(u,v)) );
+        let camera = ProjectiveCamera::new(origin, target, up, vfov, aspect, aperature);
+        let mut sampler = IndependentSampler::new(0);
+        b.iter(|| black_box(camera.get_ray(u, v, &mut sampler)) );
     } // last result: 16 ns/iter (+/- 2)
 
     #[bench]
@@ -72,6 +78,32 @@ mod benches {
         b.iter(|| black_box(sphere.hit(&ray, 0.001, 100.0)) );
     } // result: 20 ns/iter (+/- 1)
 
+    #[bench]
+    fn triangle_hit(b: &mut Bencher) {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let triangle = Triangle::new(
+            Vector3::new(-0.5, -0.5, -10.0),
+            Vector3::new( 1.5, -0.5, -10.0),
+            Vector3::new(-0.5,  1.5, -10.0),
+            material,
+        );
+        let origin = Vector3::new(0.0, 0.0, 0.0);
+        let direction = Vector3::new(0.0, 0.0, -1.0);
+        let ray = Ray::new(origin, direction);
+        b.iter(|| black_box(triangle.hit(&ray, 0.001, 100.0)) );
+    }
+
+    #[bench]
+    fn scene_hit_bvh(b: &mut Bencher) {
+        let mut scene = Scene::new();
+        utils::test_scene_setup(&mut scene);
+        scene.build_bvh();
+        let origin = Vector3::new(13.0, 2.0, 3.0);
+        let direction = Vector3::new(-13.0, -2.0, -3.0);
+        let ray = Ray::new(origin, direction);
+        b.iter(|| black_box(scene.hit(&ray, 0.001, 1000.0)) );
+    }
+
     #[bench]
     fn renderer_render(b: &mut Bencher) {
         let image = Image::new(8, 8);
@@ -83,15 +115,17 @@ mod benches {
         let vfov = 20.0;
         let aperature = 0.1;
         let aspect = image.width() as f32 / image.height() as f32;
-        let camera = Camera::new(origin, target, up, vfov, aspect, aperature);
+        let camera = ProjectiveCamera::new(origin, target, up, vfov, aspect, aperature);
 
         let mut scene = Scene::new();
         utils::test_scene_setup(&mut scene);
+        scene.build_bvh();
 
         let target = RenderTarget {
             full_width: image.width(),
             full_height: image.height(),
             buffer: image,
+            aovs: BTreeMap::new(),
         };
 
         // using: Intel(R) Core(TM) i7-10750H CPU @ 2.60GHz, 2592 Mhz
@@ -0,0 +1,191 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::image::Color;
+use crate::image::Coloru8;
+use crate::image::Image;
+use crate::image::ExrPixelType;
+use crate::scene::Scene;
+use crate::hittables::HitRecord;
+use crate::tonemap::linear_to_srgb;
+
+/// Arbitrary output variables, auxiliary passes about the first surface
+/// seen through each pixel that are rendered next to the image
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov {
+    /// interpolated normal used for shading, facing the camera
+    ShadingNormal,
+    /// normal of the surface itself, facing the camera
+    GeometricNormal,
+    /// distance along the ray to the first hit, infinite for misses
+    Depth,
+    /// world space position of the first hit, zero for misses
+    Position,
+    /// reflectance of the material at the first hit
+    Albedo,
+    /// order in which the material was first pushed into the scene, -1 for misses
+    MaterialId,
+    /// order in which the object was pushed into the scene, -1 for misses
+    ObjectId,
+    /// number of samples taken in the pixel, shown as a heatmap in 8 bit formats
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::ShadingNormal,
+        Aov::GeometricNormal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::Albedo,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::SampleCount,
+    ];
+
+    pub fn from_name(name:&str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::ShadingNormal => "shading_normal",
+            Aov::GeometricNormal => "geometric_normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::SampleCount => "sample_count",
+        }
+    }
+
+    /// channel names when written as an OpenEXR layer, single channel
+    /// passes hold the value in all three components
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::ShadingNormal | Aov::GeometricNormal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Depth => &["Z"],
+            Aov::MaterialId | Aov::ObjectId => &["id"],
+            Aov::SampleCount => &["count"],
+        }
+    }
+
+    /// filtered passes average every sample in the pixel, the others take the
+    /// sample through the pixel centre since averaging ids or depths across
+    /// an edge gives values that belong to neither side
+    pub fn is_filtered(&self) -> bool {
+        matches!(self, Aov::ShadingNormal | Aov::GeometricNormal | Aov::Albedo)
+    }
+
+    /// ids, counts and positions are stored as floats so they stay exact
+    pub fn exr_pixel_type(&self, pixel_type:ExrPixelType) -> ExrPixelType {
+        match self {
+            Aov::MaterialId | Aov::ObjectId | Aov::SampleCount | Aov::Position => ExrPixelType::Float,
+            _ => pixel_type,
+        }
+    }
+
+    /// value of the pass for a camera ray whose first hit is `hit`,
+    /// sample counts are filled in by the renderer instead
+    pub fn value(&self, scene:&Scene, hit:Option<&HitRecord>) -> Color {
+        let splat = |v:f32| Color::new(v, v, v);
+        let hit = match (self, hit) {
+            (Aov::Depth, None) => return splat(f32::INFINITY),
+            (Aov::MaterialId | Aov::ObjectId, None) => return splat(-1.0),
+            (_, None) => return Color::black(),
+            (_, Some(hit)) => hit,
+        };
+        let id = |id:Option<usize>| splat(id.map_or(-1.0, |id| id as f32));
+        match self {
+            Aov::ShadingNormal => Color::new(hit.shading_normal.x, hit.shading_normal.y, hit.shading_normal.z),
+            Aov::GeometricNormal => Color::new(hit.normal.x, hit.normal.y, hit.normal.z),
+            Aov::Depth => splat(hit.t),
+            Aov::Position => Color::new(hit.point.x, hit.point.y, hit.point.z),
+            Aov::Albedo => hit.material.albedo(hit),
+            Aov::MaterialId => id(scene.material_id(&hit.material)),
+            Aov::ObjectId => id(hit.object),
+            Aov::SampleCount => Color::black(),
+        }
+    }
+
+    /// fills the 8 bit pixels of `image`, holding this pass, with a picture
+    /// of its values for formats without linear channels
+    pub fn to_display(&self, image:&Image) {
+        let pixels: Vec<(usize, usize, Color)> = image.y_range_iter()
+            .flat_map(|y| image.x_range_iter().map(move |x| (x, y)))
+            .map(|(x, y)| (x, y, image.get_radiance(x, y)))
+            .collect();
+        let finite = |c:&Color| c.red.is_finite() && c.green.is_finite() && c.blue.is_finite();
+        let max = pixels.iter().map(|(_, _, c)| *c).filter(finite)
+            .fold(0.0, |max:f32, c| max.max(c.red).max(c.green).max(c.blue));
+        let min = pixels.iter().map(|(_, _, c)| *c).filter(finite)
+            .fold(0.0, |min:f32, c| min.min(c.red).min(c.green).min(c.blue));
+        let quantize = |v:f32| (255.0 * v.clamp(0.0, 1.0)).round() as u8;
+        let gray = |v:f32| Coloru8 { red: quantize(v), green: quantize(v), blue: quantize(v) };
+
+        for (x, y, c) in pixels {
+            let color = match self {
+                Aov::ShadingNormal | Aov::GeometricNormal => Coloru8 {
+                    red: quantize(0.5 * (c.red + 1.0)),
+                    green: quantize(0.5 * (c.green + 1.0)),
+                    blue: quantize(0.5 * (c.blue + 1.0)),
+                },
+                // near is bright, misses are black
+                Aov::Depth => match c.red.is_finite() && max > 0.0 {
+                    true => gray(1.0 - c.red / max),
+                    false => gray(0.0),
+                },
+                Aov::Position => {
+                    let range = max - min;
+                    let scale = |v:f32| match range > 0.0 { true => (v - min) / range, false => 0.0 };
+                    Coloru8 { red: quantize(scale(c.red)), green: quantize(scale(c.green)), blue: quantize(scale(c.blue)) }
+                }
+                Aov::Albedo => Coloru8 {
+                    red: quantize(linear_to_srgb(c.red)),
+                    green: quantize(linear_to_srgb(c.green)),
+                    blue: quantize(linear_to_srgb(c.blue)),
+                },
+                Aov::MaterialId | Aov::ObjectId => match c.red >= 0.0 {
+                    true => id_color(c.red.round() as u32),
+                    false => gray(0.0),
+                },
+                // a heatmap of where adaptive sampling spent its samples
+                Aov::SampleCount => match max > 0.0 {
+                    true => heat_color(c.red / max),
+                    false => gray(0.0),
+                },
+            };
+            image.set_pixel_color_u8(x, y, color);
+        }
+    }
+}
+
+/// blue through cyan, green and yellow to red as `t` goes from 0 to 1
+fn heat_color(t:f32) -> Coloru8 {
+    const RAMP: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.5],
+        [0.0, 0.8, 1.0],
+        [0.1, 0.9, 0.1],
+        [1.0, 0.9, 0.0],
+        [0.9, 0.0, 0.0],
+    ];
+    let t = t.clamp(0.0, 1.0) * (RAMP.len() - 1) as f32;
+    let i = usize::min(t as usize, RAMP.len() - 2);
+    let f = t - i as f32;
+    let [r, g, b] = [0, 1, 2].map(|c| (255.0 * (RAMP[i][c] + f * (RAMP[i+1][c] - RAMP[i][c]))).round() as u8);
+    Coloru8 { red: r, green: g, blue: b }
+}
+
+/// a bright, well spread color for every id
+fn id_color(id:u32) -> Coloru8 {
+    // integer hash so neighbouring ids get unrelated colors
+    let mut h = id.wrapping_add(1).wrapping_mul(0x9e3779b9);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    let [r, g, b, _] = h.to_le_bytes();
+    Coloru8 { red: 64 + r / 4 * 3, green: 64 + g / 4 * 3, blue: 64 + b / 4 * 3 }
+}
@@ -1,11 +1,26 @@
 
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+use serde::Serialize;
+
 use crate::ray::Ray;
-use crate::randlut::random_in_unit_disk;
+use crate::sampler::Sampler;
+use crate::sampler::sample_unit_disk;
 
 use cgmath::InnerSpace;
 
 type Vector3 = cgmath::Vector3<f32>;
 
+/// Turns points of the image into camera rays
+pub trait Camera: Send + Sync {
+    /// the ray through (`u`, `v`), which go from 0 at the centre of the
+    /// first pixel to 1 at the centre of the last, left to right and bottom
+    /// to top. None where the camera sees nothing, eg. outside the image
+    /// circle of a fisheye.
+    fn get_ray(&self, u:f32, v:f32, sampler:&mut dyn Sampler) -> Option<Ray>;
+}
+
 #[derive(Clone, Copy)]
 pub struct Viewport {
     pub width: f32,
@@ -13,8 +28,17 @@ pub struct Viewport {
     pub focal_length: f32,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+/// Perspective thin lens or orthographic camera, projecting the scene
+/// onto a flat image
 #[derive(Clone, Copy)]
-pub struct Camera {
+pub struct ProjectiveCamera {
+    projection: Projection,
     origin: Vector3,
     h_unit: Vector3,
     v_unit: Vector3,
@@ -28,7 +52,7 @@ fn deg_to_rad(deg:f32) -> f32 {
     deg * std::f32::consts::PI / 180.0
 }
 
-impl Camera {
+impl ProjectiveCamera {
     pub fn new(origin:Vector3,
                target:Vector3,
                up:Vector3,
@@ -49,7 +73,8 @@ impl Camera {
         let h_unit = direction.cross(up).normalize();
         let v_unit = h_unit.cross(direction);
 
-        Camera {
+        ProjectiveCamera {
+            projection: Projection::Perspective,
             origin,
             h_unit,
             v_unit,
@@ -60,14 +85,210 @@ impl Camera {
         }
     }
 
+    /// parallel rays looking from `origin` towards `target`, through a
+    /// `view_width` by `view_height` rectangle centred on `origin`
+    pub fn orthographic(origin:Vector3,
+                        target:Vector3,
+                        up:Vector3,
+                        view_width:f32,
+                        view_height:f32) -> Self {
+
+        let direction = (target - origin).normalize();
+        let h_unit = direction.cross(up).normalize();
+        let v_unit = h_unit.cross(direction);
+
+        ProjectiveCamera {
+            projection: Projection::Orthographic,
+            origin,
+            h_unit,
+            v_unit,
+            vp_center:     direction,
+            vp_horizontal: view_width * h_unit,
+            vp_vertical:   view_height * v_unit,
+            lens_radius:   0.0,
+        }
+    }
+}
+
+impl Camera for ProjectiveCamera {
     #[inline]
-    pub fn get_ray(&self, u:f32, v:f32) -> Ray {
+    fn get_ray(&self, u:f32, v:f32, sampler:&mut dyn Sampler) -> Option<Ray> {
+        if self.projection == Projection::Orthographic {
+            let offset = (u-0.5) * self.vp_horizontal + (v-0.5) * self.vp_vertical;
+            return Some(Ray::new(self.origin + offset, self.vp_center));
+        }
         let direction = self.vp_center 
             + (u-0.5) * self.vp_horizontal 
             + (v-0.5) * self.vp_vertical;
-        let rdisk = self.lens_radius * random_in_unit_disk();
+        let rdisk = self.lens_radius * sample_unit_disk(sampler.next_2d());
         let offset = self.h_unit*rdisk.x + self.v_unit*rdisk.y; 
-        Ray::new(self.origin + offset, direction - offset)
+        Some(Ray::new(self.origin + offset, direction - offset))
     }
+}
+
+/// How far from the centre of a fisheye image a direction lands
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FisheyeMapping {
+    /// distance proportional to the angle off the view axis
+    #[default]
+    Equidistant,
+    /// every pixel covers the same solid angle
+    Equisolid,
+}
 
+impl FisheyeMapping {
+    pub fn from_name(name:&str) -> Option<FisheyeMapping> {
+        match name {
+            "equidistant" => Some(FisheyeMapping::Equidistant),
+            "equisolid" => Some(FisheyeMapping::Equisolid),
+            _ => None,
+        }
+    }
+}
+
+/// forward, right and up unit vectors of a camera at `origin` looking at `target`
+fn frame(origin:Vector3, target:Vector3, up:Vector3) -> (Vector3, Vector3, Vector3) {
+    let forward = (target - origin).normalize();
+    let right = forward.cross(up).normalize();
+    (forward, right, right.cross(forward))
+}
+
+/// `u` given to a camera, 0 and 1 at the centres of the first and last of
+/// `n` pixels, as 0 and 1 at the outer edges of those pixels. Panoramas
+/// need it so the pixels on either side of a seam don't see the same thing.
+fn to_image_edges(u:f32, n:f32) -> f32 {
+    (u * (n - 1.0) + 0.5) / n
+}
+
+/// Every direction around `origin` as a latitude-longitude panorama, best
+/// rendered at twice as wide as high. The target is in the middle of the
+/// image, the azimuth grows to the right and the top row looks straight up.
+pub struct EquirectangularCamera {
+    origin: Vector3,
+    forward: Vector3,
+    right: Vector3,
+    up: Vector3,
+    width: f32,
+    height: f32,
+}
+
+impl EquirectangularCamera {
+    /// for an image of `width` by `height` pixels
+    pub fn new(origin:Vector3, target:Vector3, up:Vector3, width:usize, height:usize) -> Self {
+        let (forward, right, up) = frame(origin, target, up);
+        EquirectangularCamera { origin, forward, right, up, width: width as f32, height: height as f32 }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, u:f32, v:f32, _sampler:&mut dyn Sampler) -> Option<Ray> {
+        let phi = 2.0 * PI * (to_image_edges(u, self.width) - 0.5);
+        let theta = PI * (to_image_edges(v, self.height) - 0.5);
+        let horizontal = phi.sin() * self.right + phi.cos() * self.forward;
+        Some(Ray::new(self.origin, theta.cos() * horizontal + theta.sin() * self.up))
+    }
+}
+
+/// The six 90 degree views from `origin` side by side, in a strip six
+/// times as wide as high: front, right, back and left, which join up
+/// into a panorama of the horizon, then up and down. The top of the up
+/// face and the bottom of the down face are towards the back.
+pub struct CubeMapCamera {
+    origin: Vector3,
+    forward: Vector3,
+    right: Vector3,
+    up: Vector3,
+    width: f32,
+    height: f32,
+}
+
+impl CubeMapCamera {
+    /// for an image of `width` by `height` pixels, the faces are square
+    /// when `width` is six times `height`
+    pub fn new(origin:Vector3, target:Vector3, up:Vector3, width:usize, height:usize) -> Self {
+        let (forward, right, up) = frame(origin, target, up);
+        CubeMapCamera { origin, forward, right, up, width: width as f32, height: height as f32 }
+    }
+}
+
+impl Camera for CubeMapCamera {
+    fn get_ray(&self, u:f32, v:f32, _sampler:&mut dyn Sampler) -> Option<Ray> {
+        let u = 6.0 * to_image_edges(u, self.width);
+        let face = usize::min(u as usize, 5);
+        // position on the face, -1 to 1 from its bottom left corner
+        let x = 2.0 * (u - face as f32) - 1.0;
+        let y = 2.0 * to_image_edges(v, self.height) - 1.0;
+        let (f, r, t) = (self.forward, self.right, self.up);
+        let (forward, right, up) = match face {
+            0 => (f, r, t),
+            1 => (r, -f, t),
+            2 => (-f, -r, t),
+            3 => (-r, f, t),
+            4 => (t, r, -f),
+            _ => (-t, r, f),
+        };
+        Some(Ray::new(self.origin, forward + x * right + y * up))
+    }
+}
+
+/// Fisheye lens whose circular image fits the shorter side of the
+/// picture and spans `fov` degrees across, up to 360. Pixels outside
+/// the circle see nothing.
+pub struct FisheyeCamera {
+    origin: Vector3,
+    forward: Vector3,
+    right: Vector3,
+    up: Vector3,
+    width: f32,
+    height: f32,
+    /// angle off the view axis at the edge of the circle
+    max_theta: f32,
+    mapping: FisheyeMapping,
+}
+
+impl FisheyeCamera {
+    /// for an image of `width` by `height` pixels
+    pub fn new(origin:Vector3,
+               target:Vector3,
+               up:Vector3,
+               fov:f32,
+               mapping:FisheyeMapping,
+               width:usize,
+               height:usize) -> Self {
+
+        let (forward, right, up) = frame(origin, target, up);
+        FisheyeCamera {
+            origin,
+            forward,
+            right,
+            up,
+            width: width as f32,
+            height: height as f32,
+            max_theta: fov.clamp(0.0, 360.0).to_radians() / 2.0,
+            mapping,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, u:f32, v:f32, _sampler:&mut dyn Sampler) -> Option<Ray> {
+        // pixels from the centre, in radii of the image circle
+        let radius = f32::min(self.width, self.height) / 2.0;
+        let x = (to_image_edges(u, self.width) - 0.5) * self.width / radius;
+        let y = (to_image_edges(v, self.height) - 0.5) * self.height / radius;
+        let r = x.hypot(y);
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.max_theta,
+            FisheyeMapping::Equisolid => 2.0 * f32::asin(r * (self.max_theta / 2.0).sin()),
+        };
+        let sideways = match r > 0.0 {
+            true => (x * self.right + y * self.up) / r,
+            false => self.right,
+        };
+        Some(Ray::new(self.origin, theta.cos() * self.forward + theta.sin() * sideways))
+    }
 }
@@ -0,0 +1,471 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rustytracer::image::ImageFormat;
+use rustytracer::image::ExrPixelType;
+use rustytracer::tonemap::ToneMapping;
+use rustytracer::description::IntegratorDescription;
+use rustytracer::description::DenoiserDescription;
+use rustytracer::description::ProjectionDescription;
+use rustytracer::aov::Aov;
+use rustytracer::camera::FisheyeMapping;
+use rustytracer::driver::TileOrder;
+use rustytracer::sampler::SamplerType;
+use rustytracer::filter::FilterType;
+
+pub const USAGE: &str = "\
+usage: rustytracer [OPTIONS] [SCENE]
+
+Renders SCENE, a .json, .toml or .ron scene description. Without a scene
+the built-in random spheres scene is rendered.
+
+options:
+  -o, --output <PATH>          output image path [default: traced.bmp]
+  -f, --format <FORMAT>        output format, otherwise taken from the output
+                               extension [possible values: bmp, png, exr, hdr]
+      --exr-pixel-type <TYPE>  precision of exr channels [default: half]
+                               [possible values: half, float]
+      --aov <NAMES>            comma separated passes to render next to the
+                               image, or all. Exr output holds them as layers,
+                               other formats get a file per pass such as
+                               traced.depth.bmp [possible values:
+                               shading_normal, geometric_normal, depth,
+                               position, albedo, material_id, object_id,
+                               sample_count]
+  -W, --width <PIXELS>         image width
+  -H, --height <PIXELS>        image height, if only one of width or height is
+                               given the other follows the scene's aspect ratio
+  -s, --samples <N>            samples per pixel
+  -d, --max-depth <N>          maximum number of bounces per path
+      --sampler <NAME>         where pixel, lens and path samples come from
+                               [default: sobol] [possible values: independent,
+                               stratified, halton, sobol, blue_noise]
+      --filter <NAME>          pixel reconstruction filter, samples are spread
+                               over the pixels within its radius [default: box]
+                               [possible values: box, tent, gaussian, mitchell,
+                               lanczos]
+      --filter-radius <PIXELS> filter radius, defaults to 0.5 for box, 1 for
+                               tent, 1.5 for gaussian, 2 for mitchell and 3 for
+                               lanczos
+      --adaptive <THRESHOLD>   stop sampling pixels whose relative error is
+                               below THRESHOLD, --samples is then the most a
+                               pixel gets, off to sample every pixel fully.
+                               --aov sample_count shows where samples went
+      --min-samples <N>        samples per pixel before adaptive sampling may
+                               stop, implies --adaptive [default: 16]
+  -i, --integrator <NAME>      how camera rays are shaded [possible values: path,
+                               direct, ao, shading_normal, geometric_normal, uv,
+                               depth, front_face]
+      --time-limit <SECONDS>   stop sampling after this long, pixels not reached
+                               yet get a single sample
+      --progressive <N>        render the whole frame in passes of N samples per
+                               pixel and write the output after every pass
+      --denoise <NAME>         filter the finished image, guided by normal,
+                               albedo and depth passes [possible values: none,
+                               atrous]
+  -t, --tile-size <PIXELS>     edge length of the square render tiles [default: 64]
+      --tile-order <ORDER>     order tiles are rendered in [default: scanline]
+                               [possible values: scanline, spiral, hilbert]
+  -j, --threads <N>            worker threads [default: available parallelism]
+      --seed <N>               random seed
+      --exposure <STOPS>       exposure adjustment before tone mapping
+      --tone-mapping <OP>      operator compressing highlights for 8 bit output
+                               [possible values: clamp, reinhard, aces, agx]
+
+camera overrides:
+      --camera-origin <X,Y,Z>  camera position
+      --camera-target <X,Y,Z>  point the camera looks at
+      --camera-up <X,Y,Z>      camera up vector
+      --projection <NAME>      camera projection, equirectangular and cube_map
+                               images are 2:1 and 6:1 when only --width or
+                               --height is given [default: perspective]
+                               [possible values: perspective, orthographic,
+                               equirectangular, cube_map, fisheye]
+      --view-width <SIZE>      width of the orthographic view in scene units,
+                               implies --projection orthographic
+      --view-height <SIZE>     height of the orthographic view, if only one of
+                               width or height is given the other follows the
+                               image's aspect ratio
+      --fisheye-fov <DEGREES>  field of view across the fisheye image circle,
+                               up to 360, implies --projection fisheye
+                               [default: 180]
+      --fisheye-mapping <NAME> how angles off the view axis map to the image
+                               [default: equidistant] [possible values:
+                               equidistant, equisolid]
+      --vfov <DEGREES>         vertical field of view
+      --aperture <SIZE>        lens aperture, 0 for a pinhole camera
+      --focus-distance <DIST>  distance to the plane of focus
+
+  -h, --help                   print this help
+";
+
+// every option apart from --help takes a value
+const OPTIONS: &[&str] = &[
+    "-o", "--output",
+    "-f", "--format",
+    "--exr-pixel-type",
+    "--aov",
+    "-W", "--width",
+    "-H", "--height",
+    "-s", "--samples",
+    "-d", "--max-depth",
+    "--sampler",
+    "--filter",
+    "--filter-radius",
+    "--adaptive",
+    "--min-samples",
+    "-i", "--integrator",
+    "--time-limit",
+    "--progressive",
+    "--denoise",
+    "-t", "--tile-size",
+    "--tile-order",
+    "-j", "--threads",
+    "--seed",
+    "--exposure",
+    "--tone-mapping",
+    "--camera-origin",
+    "--camera-target",
+    "--camera-up",
+    "--projection",
+    "--view-width",
+    "--view-height",
+    "--fisheye-fov",
+    "--fisheye-mapping",
+    "--vfov",
+    "--aperture",
+    "--focus-distance",
+];
+
+#[derive(Debug)]
+pub struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl CliError {
+    pub fn new(message:impl Into<String>) -> CliError {
+        CliError(message.into())
+    }
+}
+
+#[derive(Default)]
+pub struct CameraOverrides {
+    pub origin: Option<[f32; 3]>,
+    pub target: Option<[f32; 3]>,
+    pub up: Option<[f32; 3]>,
+    pub projection: Option<ProjectionDescription>,
+    pub view_width: Option<f32>,
+    pub view_height: Option<f32>,
+    pub fisheye_fov: Option<f32>,
+    pub fisheye_mapping: Option<FisheyeMapping>,
+    pub vfov: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_distance: Option<f32>,
+}
+
+pub struct Options {
+    pub scene: Option<String>,
+    pub output: String,
+    pub format: ImageFormat,
+    pub aovs: Vec<Aov>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub sampler: Option<SamplerType>,
+    pub filter: Option<FilterType>,
+    pub filter_radius: Option<f32>,
+    /// Some(None) turns off adaptive sampling the scene asks for
+    pub adaptive: Option<Option<f32>>,
+    pub min_samples: Option<usize>,
+    pub integrator: Option<IntegratorDescription>,
+    /// Some(None) turns off a denoiser the scene asks for
+    pub denoiser: Option<Option<DenoiserDescription>>,
+    pub time_limit: Option<Duration>,
+    /// samples per pixel in each pass of a progressive render
+    pub progressive: Option<usize>,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub exposure: Option<f32>,
+    pub tone_mapping: Option<ToneMapping>,
+    pub camera: CameraOverrides,
+}
+
+pub enum Command {
+    Help,
+    Render(Box<Options>),
+}
+
+fn parse_value<T: FromStr>(option:&str, value:&str) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError::new(format!("invalid value '{}' for {}", value, option)))
+}
+
+fn parse_positive(option:&str, value:&str) -> Result<usize, CliError> {
+    match parse_value(option, value)? {
+        0 => Err(CliError::new(format!("{} must be greater than 0", option))),
+        n => Ok(n),
+    }
+}
+
+fn parse_vector(option:&str, value:&str) -> Result<[f32; 3], CliError> {
+    let components: Vec<&str> = value.split(',').collect();
+    if components.len() != 3 {
+        return Err(CliError::new(format!("{} expects three comma separated numbers, got '{}'", option, value)));
+    }
+    let mut v = [0.0; 3];
+    for (i, c) in components.iter().enumerate() {
+        v[i] = parse_value(option, c.trim())?;
+    }
+    Ok(v)
+}
+
+pub fn parse(args:&[String]) -> Result<Command, CliError> {
+    let mut scene = None;
+    let mut output = None;
+    let mut format = None;
+    let mut exr_pixel_type = None;
+    let mut aovs = Vec::new();
+    let mut width = None;
+    let mut height = None;
+    let mut samples = None;
+    let mut max_depth = None;
+    let mut sampler = None;
+    let mut filter = None;
+    let mut filter_radius = None;
+    let mut adaptive = None;
+    let mut min_samples = None;
+    let mut integrator = None;
+    let mut denoiser = None;
+    let mut time_limit = None;
+    let mut progressive = None;
+    let mut tile_size = 64;
+    let mut tile_order = TileOrder::default();
+    let mut threads = None;
+    let mut seed = None;
+    let mut exposure = None;
+    let mut tone_mapping = None;
+    let mut camera = CameraOverrides::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if scene.is_some() {
+                return Err(CliError::new(format!("unexpected argument '{}', only one scene may be given", arg)));
+            }
+            scene = Some(arg.clone());
+            continue;
+        }
+
+        // accept both `--option value` and `--option=value`
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if arg.starts_with("--") => (option, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        if option == "-h" || option == "--help" {
+            return Ok(Command::Help);
+        }
+        if !OPTIONS.contains(&option) {
+            return Err(CliError::new(format!("unknown option '{}'", option)));
+        }
+        let value = match inline_value {
+            Some(value) => value,
+            None => match args.next() {
+                Some(value) => value.clone(),
+                None => return Err(CliError::new(format!("missing value for {}", option))),
+            },
+        };
+        let value = value.as_str();
+
+        match option {
+            "-o" | "--output" => output = Some(value.to_string()),
+            "-f" | "--format" => {
+                format = Some(ImageFormat::from_extension(value)
+                    .ok_or_else(|| CliError::new(format!("unsupported output format '{}'", value)))?);
+            }
+            "--exr-pixel-type" => {
+                exr_pixel_type = match value {
+                    "half" => Some(ExrPixelType::Half),
+                    "float" => Some(ExrPixelType::Float),
+                    _ => return Err(CliError::new(format!("unknown exr pixel type '{}', expected half or float", value))),
+                };
+            }
+            "--aov" => {
+                for name in value.split(',').map(str::trim) {
+                    match name {
+                        "all" => aovs.extend(Aov::ALL),
+                        _ => aovs.push(Aov::from_name(name)
+                            .ok_or_else(|| CliError::new(format!("unknown aov '{}'", name)))?),
+                    }
+                }
+            }
+            "-W" | "--width" => width = Some(parse_positive(option, value)?),
+            "-H" | "--height" => height = Some(parse_positive(option, value)?),
+            "-s" | "--samples" => samples = Some(parse_positive(option, value)?),
+            "-d" | "--max-depth" => max_depth = Some(parse_positive(option, value)?),
+            "--sampler" => {
+                sampler = Some(SamplerType::from_name(value)
+                    .ok_or_else(|| CliError::new(format!("unknown sampler '{}'", value)))?);
+            }
+            "--filter" => {
+                filter = Some(FilterType::from_name(value)
+                    .ok_or_else(|| CliError::new(format!("unknown filter '{}'", value)))?);
+            }
+            "--filter-radius" => {
+                let radius: f32 = parse_value(option, value)?;
+                if !radius.is_finite() || radius <= 0.0 {
+                    return Err(CliError::new(format!("{} must be greater than 0", option)));
+                }
+                filter_radius = Some(radius);
+            }
+            "--adaptive" => {
+                adaptive = match value {
+                    "off" => Some(None),
+                    _ => {
+                        let threshold: f32 = parse_value(option, value)?;
+                        if threshold.is_nan() || threshold <= 0.0 {
+                            return Err(CliError::new(format!("{} must be greater than 0 or off", option)));
+                        }
+                        Some(Some(threshold))
+                    }
+                };
+            }
+            "--min-samples" => min_samples = Some(parse_positive(option, value)?),
+            "-i" | "--integrator" => {
+                integrator = Some(IntegratorDescription::from_name(value)
+                    .ok_or_else(|| CliError::new(format!("unknown integrator '{}'", value)))?);
+            }
+            "--denoise" => {
+                denoiser = match value {
+                    "none" => Some(None),
+                    _ => Some(Some(DenoiserDescription::from_name(value)
+                        .ok_or_else(|| CliError::new(format!("unknown denoiser '{}'", value)))?)),
+                };
+            }
+            "--time-limit" => {
+                let seconds: f32 = parse_value(option, value)?;
+                time_limit = match Duration::try_from_secs_f32(seconds) {
+                    Ok(limit) if seconds > 0.0 => Some(limit),
+                    _ => return Err(CliError::new(format!("{} must be a positive number of seconds", option))),
+                };
+            }
+            "--progressive" => progressive = Some(parse_positive(option, value)?),
+            "-t" | "--tile-size" => tile_size = parse_positive(option, value)?,
+            "--tile-order" => {
+                tile_order = TileOrder::from_name(value)
+                    .ok_or_else(|| CliError::new(format!("unknown tile order '{}'", value)))?;
+            }
+            "-j" | "--threads" => threads = Some(parse_positive(option, value)?),
+            "--seed" => seed = Some(parse_value(option, value)?),
+            "--exposure" => {
+                let stops: f32 = parse_value(option, value)?;
+                if !stops.is_finite() {
+                    return Err(CliError::new(format!("{} must be a finite number of stops", option)));
+                }
+                exposure = Some(stops);
+            }
+            "--tone-mapping" => {
+                tone_mapping = Some(ToneMapping::from_name(value)
+                    .ok_or_else(|| CliError::new(format!("unknown tone mapping '{}'", value)))?);
+            }
+            "--camera-origin" => camera.origin = Some(parse_vector(option, value)?),
+            "--camera-target" => camera.target = Some(parse_vector(option, value)?),
+            "--camera-up" => camera.up = Some(parse_vector(option, value)?),
+            "--projection" => {
+                camera.projection = Some(ProjectionDescription::from_name(value)
+                    .ok_or_else(|| CliError::new(format!("unknown projection '{}'", value)))?);
+            }
+            "--view-width" | "--view-height" => {
+                let size: f32 = parse_value(option, value)?;
+                if !size.is_finite() || size <= 0.0 {
+                    return Err(CliError::new(format!("{} must be greater than 0", option)));
+                }
+                match option {
+                    "--view-width" => camera.view_width = Some(size),
+                    _ => camera.view_height = Some(size),
+                }
+            }
+            "--fisheye-fov" => {
+                let fov: f32 = parse_value(option, value)?;
+                if fov.is_nan() || fov <= 0.0 || fov > 360.0 {
+                    return Err(CliError::new(format!("{} must be between 0 and 360 degrees", option)));
+                }
+                camera.fisheye_fov = Some(fov);
+            }
+            "--fisheye-mapping" => {
+                camera.fisheye_mapping = Some(FisheyeMapping::from_name(value)
+                    .ok_or_else(|| CliError::new(format!("unknown fisheye mapping '{}'", value)))?);
+            }
+            "--vfov" => {
+                let vfov: f32 = parse_value(option, value)?;
+                if vfov.is_nan() || vfov <= 0.0 || vfov >= 180.0 {
+                    return Err(CliError::new(format!("{} must be between 0 and 180 degrees", option)));
+                }
+                camera.vfov = Some(vfov);
+            }
+            "--aperture" => {
+                let aperture: f32 = parse_value(option, value)?;
+                if aperture.is_nan() || aperture < 0.0 {
+                    return Err(CliError::new(format!("{} must not be negative", option)));
+                }
+                camera.aperture = Some(aperture);
+            }
+            "--focus-distance" => {
+                let distance: f32 = parse_value(option, value)?;
+                if distance.is_nan() || distance <= 0.0 {
+                    return Err(CliError::new(format!("{} must be greater than 0", option)));
+                }
+                camera.focus_distance = Some(distance);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    let output = output.unwrap_or_else(|| "traced.bmp".to_string());
+    let format = match format {
+        Some(format) => format,
+        None => ImageFormat::from_path(&output).ok_or_else(|| {
+            CliError::new(format!("can't tell the image format of '{}', use a known extension or --format", output))
+        })?,
+    };
+    let format = match (format, exr_pixel_type) {
+        (ImageFormat::Exr(_), Some(pixel_type)) => ImageFormat::Exr(pixel_type),
+        (_, Some(_)) => return Err(CliError::new("--exr-pixel-type only applies to exr output")),
+        (format, None) => format,
+    };
+
+    Ok(Command::Render(Box::new(Options {
+        scene,
+        output,
+        format,
+        aovs,
+        width,
+        height,
+        samples,
+        max_depth,
+        sampler,
+        filter,
+        filter_radius,
+        adaptive,
+        min_samples,
+        integrator,
+        denoiser,
+        time_limit,
+        progressive,
+        tile_size,
+        tile_order,
+        threads,
+        seed,
+        exposure,
+        tone_mapping,
+        camera,
+    })))
+}
@@ -0,0 +1,370 @@
+// deflate (rfc 1951) compressor wrapped in a zlib (rfc 1950) stream,
+// lz77 over a hash chain followed by one dynamic huffman block per
+// run of tokens
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: usize = 15;
+const TOKENS_PER_BLOCK: usize = 1 << 16;
+
+const END_OF_BLOCK: usize = 256;
+const NUM_LITLEN_CODES: usize = 286;
+const NUM_DIST_CODES: usize = 30;
+const NUM_CODELEN_CODES: usize = 19;
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODELEN_LENGTH: u8 = 7;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// order in which the code length code lengths are stored
+const CODELEN_ORDER: [usize; NUM_CODELEN_CODES] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length:u16, distance:u16 },
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn new(bytes:Vec<u8>) -> BitWriter {
+        BitWriter { bytes, buffer: 0, nbits: 0 }
+    }
+
+    /// appends the low `n` bits of `value`, least significant bit first
+    #[inline]
+    fn write(&mut self, value:u32, n:u8) {
+        self.buffer |= (value as u64) << self.nbits;
+        self.nbits += n as u32;
+        while self.nbits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// canonical huffman code, codes are stored bit reversed ready to be written
+struct Huffman {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl Huffman {
+    /// builds a code whose lengths don't exceed `max_length` by flattening
+    /// the frequencies until the tree is shallow enough
+    fn new(freqs:&[u32], max_length:u8) -> Huffman {
+        let mut freqs = freqs.to_vec();
+        loop {
+            let lengths = huffman_lengths(&freqs);
+            if lengths.iter().all(|&l| l <= max_length) {
+                let codes = canonical_codes(&lengths);
+                return Huffman { lengths, codes };
+            }
+            for f in freqs.iter_mut().filter(|f| **f > 0) {
+                *f = (*f >> 1) | 1;
+            }
+        }
+    }
+
+    #[inline]
+    fn write(&self, out:&mut BitWriter, symbol:usize) {
+        out.write(self.codes[symbol] as u32, self.lengths[symbol]);
+    }
+}
+
+fn huffman_lengths(freqs:&[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let used: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    if used.len() < 2 {
+        // a lone symbol still gets a complete two code tree, zlib
+        // refuses incomplete code length codes
+        let symbol = used.first().copied().unwrap_or(0);
+        lengths[symbol] = 1;
+        lengths[if symbol == 0 { 1 } else { 0 }] = 1;
+        return lengths;
+    }
+
+    // nodes are (weight, index), leaves first, internal nodes remember their children
+    let mut weights: Vec<u64> = used.iter().map(|&i| freqs[i] as u64).collect();
+    let mut parents = vec![usize::MAX; used.len()];
+    let mut heap: std::collections::BinaryHeap<std::cmp::Reverse<(u64, usize)>> =
+        (0..used.len()).map(|i| std::cmp::Reverse((weights[i], i))).collect();
+    while heap.len() > 1 {
+        let std::cmp::Reverse((wa, a)) = heap.pop().unwrap();
+        let std::cmp::Reverse((wb, b)) = heap.pop().unwrap();
+        let node = weights.len();
+        weights.push(wa + wb);
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(std::cmp::Reverse((wa + wb, node)));
+    }
+
+    // parents always come after their children, so walk down from the root
+    let mut depths = vec![0usize; weights.len()];
+    for node in (0..weights.len()).rev() {
+        if parents[node] != usize::MAX {
+            depths[node] = depths[parents[node]] + 1;
+        }
+    }
+    for (leaf, &symbol) in used.iter().enumerate() {
+        lengths[symbol] = depths[leaf].min(u8::MAX as usize) as u8;
+    }
+    lengths
+}
+
+fn canonical_codes(lengths:&[u8]) -> Vec<u16> {
+    let max = *lengths.iter().max().unwrap_or(&0) as usize;
+    let mut count = vec![0u16; max + 1];
+    for &l in lengths {
+        count[l as usize] += 1;
+    }
+    count[0] = 0;
+    let mut next = vec![0u16; max + 1];
+    let mut code = 0u16;
+    for bits in 1..=max {
+        code = (code + count[bits-1]) << 1;
+        next[bits] = code;
+    }
+    lengths.iter().map( |&l| {
+        if l == 0 {
+            return 0;
+        }
+        let code = next[l as usize];
+        next[l as usize] += 1;
+        code.reverse_bits() >> (16 - l as u32)
+    }).collect()
+}
+
+#[inline]
+fn length_symbol(length:u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= length) - 1
+}
+
+#[inline]
+fn distance_symbol(distance:u16) -> usize {
+    DIST_BASE.partition_point(|&base| base <= distance) - 1
+}
+
+#[inline]
+fn hash(data:&[u8], i:usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i+1] as u32) << 8 | data[i+2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// greedy lz77 parse, each position only keeps the most recent
+/// `MAX_CHAIN` candidates with the same three byte prefix
+fn tokenize(data:&[u8]) -> Vec<Token> {
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let insert = |i:usize, head:&mut [usize], prev:&mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(data, i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_length = usize::min(MAX_MATCH, data.len() - i);
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..candidate + max_length].iter()
+                    .zip(&data[i..i + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        match best_length >= MIN_MATCH {
+            true => {
+                tokens.push(Token::Match { length: best_length as u16, distance: best_distance as u16 });
+                for j in i..i + best_length {
+                    insert(j, &mut head, &mut prev);
+                }
+                i += best_length;
+            }
+            false => {
+                tokens.push(Token::Literal(data[i]));
+                insert(i, &mut head, &mut prev);
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// run length encodes the concatenated code lengths with the symbols 16, 17 and 18,
+/// returns (symbol, extra bits value)
+fn encode_code_lengths(lengths:&[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let l = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&x| x == l).count();
+        if l == 0 && run >= 3 {
+            let n = run.min(138);
+            match n >= 11 {
+                true => out.push((18, (n - 11) as u8)),
+                false => out.push((17, (n - 3) as u8)),
+            }
+            i += n;
+        } else if l != 0 && run >= 4 {
+            // the first length is sent as is, then repeated
+            out.push((l, 0));
+            let n = (run - 1).min(6);
+            out.push((16, (n - 3) as u8));
+            i += 1 + n;
+        } else {
+            out.push((l, 0));
+            i += 1;
+        }
+    }
+    out
+}
+
+fn write_block(out:&mut BitWriter, tokens:&[Token], last:bool) {
+    let mut litlen_freqs = [0u32; NUM_LITLEN_CODES];
+    let mut dist_freqs = [0u32; NUM_DIST_CODES];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => litlen_freqs[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                litlen_freqs[257 + length_symbol(length)] += 1;
+                dist_freqs[distance_symbol(distance)] += 1;
+            }
+        }
+    }
+    litlen_freqs[END_OF_BLOCK] += 1;
+
+    let litlen = Huffman::new(&litlen_freqs, MAX_CODE_LENGTH);
+    let dist = Huffman::new(&dist_freqs, MAX_CODE_LENGTH);
+
+    let hlit = 257 + litlen.lengths[257..].iter().rposition(|&l| l > 0).map_or(0, |p| p + 1);
+    let hdist = 1 + dist.lengths[1..].iter().rposition(|&l| l > 0).map_or(0, |p| p + 1);
+
+    let mut lengths = litlen.lengths[..hlit].to_vec();
+    lengths.extend_from_slice(&dist.lengths[..hdist]);
+    let rle = encode_code_lengths(&lengths);
+
+    let mut codelen_freqs = [0u32; NUM_CODELEN_CODES];
+    for &(symbol, _) in &rle {
+        codelen_freqs[symbol as usize] += 1;
+    }
+    let codelen = Huffman::new(&codelen_freqs, MAX_CODELEN_LENGTH);
+    let hclen = CODELEN_ORDER.iter().rposition(|&s| codelen.lengths[s] > 0).map_or(0, |p| p + 1).max(4);
+
+    out.write(last as u32, 1);
+    out.write(2, 2); // dynamic huffman
+    out.write((hlit - 257) as u32, 5);
+    out.write((hdist - 1) as u32, 5);
+    out.write((hclen - 4) as u32, 4);
+    for &symbol in &CODELEN_ORDER[..hclen] {
+        out.write(codelen.lengths[symbol] as u32, 3);
+    }
+    for &(symbol, extra) in &rle {
+        codelen.write(out, symbol as usize);
+        match symbol {
+            16 => out.write(extra as u32, 2),
+            17 => out.write(extra as u32, 3),
+            18 => out.write(extra as u32, 7),
+            _ => (),
+        }
+    }
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => litlen.write(out, byte as usize),
+            Token::Match { length, distance } => {
+                let ls = length_symbol(length);
+                litlen.write(out, 257 + ls);
+                out.write((length - LENGTH_BASE[ls]) as u32, LENGTH_EXTRA[ls]);
+                let ds = distance_symbol(distance);
+                dist.write(out, ds);
+                out.write((distance - DIST_BASE[ds]) as u32, DIST_EXTRA[ds]);
+            }
+        }
+    }
+    litlen.write(out, END_OF_BLOCK);
+}
+
+pub fn adler32(data:&[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b overflows
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+/// compresses `data` into a zlib stream
+pub fn zlib_compress(data:&[u8]) -> Vec<u8> {
+    // deflate with a 32k window, default compression level, no dictionary
+    let mut out = BitWriter::new(vec![0x78, 0x9c]);
+
+    let tokens = tokenize(data);
+    if tokens.is_empty() {
+        write_block(&mut out, &[], true);
+    }
+    let nblocks = tokens.len().div_ceil(TOKENS_PER_BLOCK);
+    for (i, block) in tokens.chunks(TOKENS_PER_BLOCK).enumerate() {
+        write_block(&mut out, block, i + 1 == nblocks);
+    }
+
+    let mut bytes = out.finish();
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
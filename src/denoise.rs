@@ -0,0 +1,222 @@
+use crate::aov::Aov;
+use crate::image::Color;
+use crate::image::Image;
+use crate::image::Region;
+use crate::renderer::RenderTarget;
+
+/// weights of the B3 spline the wavelet is built from
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// albedo below this is treated as black and not divided out
+const MIN_ALBEDO: f32 = 0.01;
+
+/// Edge avoiding à-trous wavelet filter after Dammertz et al. 2010. Every
+/// iteration blurs with a 5x5 kernel whose taps spread twice as far as in
+/// the one before, while differences in color, normal, albedo and depth
+/// keep the blur from crossing edges. Like SVGF, color differences are
+/// measured against a local estimate of the noise which is filtered along
+/// with the image. Lighting is filtered with the albedo divided out so
+/// textures stay sharp, reflections and refractions are only told apart
+/// by their color since the guides describe the first surface hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtrousFilter {
+    pub iterations: usize,
+    /// tolerated luminance difference in standard deviations of the noise
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+    /// tolerated depth difference relative to the depth of the pixel
+    pub sigma_depth: f32,
+}
+
+impl Default for AtrousFilter {
+    fn default() -> Self {
+        AtrousFilter {
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+/// the per pixel features an iteration compares
+struct Features {
+    width: usize,
+    height: usize,
+    normal: Option<Vec<[f32; 3]>>,
+    albedo: Option<Vec<[f32; 3]>>,
+    depth: Option<Vec<f32>>,
+}
+
+impl AtrousFilter {
+    /// passes the filter uses to find edges, render them next to the image
+    pub const GUIDES: [Aov; 3] = [Aov::ShadingNormal, Aov::Albedo, Aov::Depth];
+
+    pub fn new(iterations:usize) -> AtrousFilter {
+        AtrousFilter { iterations, ..AtrousFilter::default() }
+    }
+
+    /// denoised copy of the target's image, the 8 bit pixels are left black.
+    /// Guides missing from the target are left out of the edge stopping.
+    pub fn denoise(&self, target:&RenderTarget) -> Image {
+        let image = &target.buffer;
+        let (width, height) = (image.width(), image.height());
+        let x0 = image.x_range_iter().start;
+        let y0 = image.y_range_iter().start;
+        let read = |buffer:&Image| -> Vec<[f32; 3]> {
+            buffer.y_range_iter()
+                .flat_map(|y| buffer.x_range_iter().map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let c = buffer.get_radiance(x, y);
+                    [c.red, c.green, c.blue]
+                })
+                .collect()
+        };
+
+        let features = Features {
+            width,
+            height,
+            normal: target.aovs.get(&Aov::ShadingNormal).map(read),
+            albedo: target.aovs.get(&Aov::Albedo).map(read),
+            depth: target.aovs.get(&Aov::Depth).map(|depth| read(depth).iter().map(|d| d[0]).collect()),
+        };
+
+        // filter the lighting rather than the textured color
+        let modulation: Vec<[f32; 3]> = match &features.albedo {
+            Some(albedo) => albedo.iter().map(|a| a.map(|c| match c > MIN_ALBEDO { true => c, false => 1.0 })).collect(),
+            None => vec![[1.0; 3]; width * height],
+        };
+        let color: Vec<[f32; 3]> = read(image).iter().zip(&modulation)
+            .map(|(c, m)| [0, 1, 2].map(|i| match c[i].is_finite() { true => c[i] / m[i], false => 0.0 }))
+            .collect();
+
+        // rgb and the variance of the luminance
+        let mut color = estimate_variance(&color, width, height);
+        for iteration in 0..self.iterations {
+            color = self.iterate(&color, &features, iteration);
+        }
+
+        let denoised = Image::new_with_region(Region { x: x0, y: y0, width, height });
+        for (i, (c, m)) in color.iter().zip(&modulation).enumerate() {
+            let (x, y) = (x0 + i % width, y0 + i / width);
+            // keep the sample weights so the result reads like the original
+            let weight = image.get_weight(x, y);
+            let c = Color::new(c[0] * m[0], c[1] * m[1], c[2] * m[2]);
+            denoised.add_radiance(x, y, weight * c, weight);
+        }
+        denoised
+    }
+
+    /// one pass of the wavelet with taps `2^iteration` pixels apart, rows are split between threads
+    fn iterate(&self, color:&[[f32; 4]], features:&Features, iteration:usize) -> Vec<[f32; 4]> {
+        let (width, height) = (features.width, features.height);
+        let mut out = vec![[0.0; 4]; width * height];
+        let nthreads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let rows_per_thread = height.div_ceil(nthreads).max(1);
+
+        std::thread::scope( |scope| {
+            for (chunk, rows) in out.chunks_mut(rows_per_thread * width).enumerate() {
+                scope.spawn(move || {
+                    let y_start = chunk * rows_per_thread;
+                    for (i, pixel) in rows.iter_mut().enumerate() {
+                        let p = y_start * width + i;
+                        *pixel = self.filter_pixel(color, features, iteration, p);
+                    }
+                });
+            }
+        });
+        out
+    }
+
+    fn filter_pixel(&self, color:&[[f32; 4]], features:&Features, iteration:usize, p:usize) -> [f32; 4] {
+        let (width, height) = (features.width, features.height);
+        let step = 1isize << iteration;
+        let (px, py) = ((p % width) as isize, (p / width) as isize);
+
+        let lp = luminance(&color[p]);
+        let inv_color = 1.0 / (self.sigma_color * color[p][3].max(0.0).sqrt() + 1e-4);
+        let inv_normal = 1.0 / (self.sigma_normal * self.sigma_normal).max(1e-12);
+        let inv_albedo = 1.0 / (self.sigma_albedo * self.sigma_albedo).max(1e-12);
+
+        let mut sum = [0.0; 3];
+        let mut variance = 0.0;
+        let mut total = 0.0;
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let qy = py + (j as isize - 2) * step;
+            if qy < 0 || qy >= height as isize {
+                continue;
+            }
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let qx = px + (i as isize - 2) * step;
+                if qx < 0 || qx >= width as isize {
+                    continue;
+                }
+                let q = qx as usize + qy as usize * width;
+
+                let mut exponent = (lp - luminance(&color[q])).abs() * inv_color;
+                if let Some(normal) = &features.normal {
+                    exponent += distance2(&normal[p], &normal[q]) * inv_normal;
+                }
+                if let Some(albedo) = &features.albedo {
+                    exponent += distance2(&albedo[p], &albedo[q]) * inv_albedo;
+                }
+                if let Some(depth) = &features.depth {
+                    let (dp, dq) = (depth[p], depth[q]);
+                    match (dp.is_finite(), dq.is_finite()) {
+                        (true, true) => exponent += (dp - dq).abs() / (self.sigma_depth * dp.max(1e-6)),
+                        // background only blends with background
+                        (false, false) => (),
+                        _ => continue,
+                    }
+                }
+
+                let weight = kx * ky * (-exponent).exp();
+                for c in 0..3 {
+                    sum[c] += weight * color[q][c];
+                }
+                variance += weight * weight * color[q][3];
+                total += weight;
+            }
+        }
+        match total > 0.0 {
+            true => [sum[0] / total, sum[1] / total, sum[2] / total, variance / (total * total)],
+            false => color[p],
+        }
+    }
+}
+
+#[inline]
+fn luminance(c:&[f32; 4]) -> f32 {
+    crate::tonemap::luminance([c[0], c[1], c[2]])
+}
+
+/// appends the variance of the luminance in the 3x3 neighbourhood of each
+/// pixel, a stand-in for the variance of its samples
+fn estimate_variance(color:&[[f32; 3]], width:usize, height:usize) -> Vec<[f32; 4]> {
+    (0..width * height).map( |p| {
+        let (px, py) = (p % width, p / width);
+        let mut sum = 0.0;
+        let mut sum2 = 0.0;
+        let mut n = 0.0;
+        for qy in py.saturating_sub(1)..usize::min(py + 2, height) {
+            for qx in px.saturating_sub(1)..usize::min(px + 2, width) {
+                let c = color[qx + qy * width];
+                let l = crate::tonemap::luminance(c);
+                sum += l;
+                sum2 += l * l;
+                n += 1.0;
+            }
+        }
+        let mean = sum / n;
+        let [r, g, b] = color[p];
+        [r, g, b, f32::max(sum2 / n - mean * mean, 0.0)]
+    }).collect()
+}
+
+#[inline]
+fn distance2(a:&[f32; 3], b:&[f32; 3]) -> f32 {
+    let d = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    d[0] * d[0] + d[1] * d[1] + d[2] * d[2]
+}
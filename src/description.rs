@@ -0,0 +1,786 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde::Serialize;
+use cgmath::InnerSpace;
+
+use crate::obj;
+use crate::obj::ObjError;
+use crate::scene::Scene;
+use crate::image::Color;
+use crate::camera::Camera;
+use crate::camera::CubeMapCamera;
+use crate::camera::FisheyeCamera;
+use crate::camera::FisheyeMapping;
+use crate::camera::ProjectiveCamera;
+use crate::camera::EquirectangularCamera;
+use crate::renderer::Renderer;
+use crate::renderer::AdaptiveSampling;
+use crate::sampler::SamplerType;
+use crate::filter::Filter;
+use crate::filter::FilterType;
+use crate::integrators::Integrator;
+use crate::integrators::DebugView;
+use crate::integrators::PathTracer;
+use crate::integrators::DirectLighting;
+use crate::integrators::DebugIntegrator;
+use crate::integrators::AmbientOcclusion;
+use crate::denoise::AtrousFilter;
+use crate::tonemap::ToneMapping;
+use crate::tonemap::DisplayTransform;
+use crate::hittables::Sphere;
+use crate::hittables::Triangle;
+use crate::materials::Material;
+use crate::textures::Texture;
+use crate::textures::NoiseKind;
+use crate::textures::SolidColor;
+use crate::textures::NoiseTexture;
+use crate::textures::ImageTexture;
+use crate::textures::CheckerTexture;
+use crate::textures::TextureMapping;
+use crate::environment::Environment;
+use crate::environment::ImageEnvironment;
+use crate::environment::ConstantEnvironment;
+use crate::environment::GradientEnvironment;
+use crate::sky::SkyParameters;
+use crate::sky::SkyEnvironment;
+use crate::materials::Metal;
+use crate::materials::Lambertian;
+use crate::materials::Dialectric;
+use crate::materials::DiffuseLight;
+
+type Vector3 = cgmath::Vector3<f32>;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    UnsupportedFormat(PathBuf),
+    Parse(String),
+    Serialize(String),
+    UnknownMaterial(String),
+    UnknownTexture(String),
+    Invalid(String),
+    Obj(ObjError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "couldn't access {}: {}", path.display(), source),
+            SceneError::UnsupportedFormat(path) => {
+                write!(f, "{}: unsupported scene format, expected .json, .toml or .ron", path.display())
+            }
+            SceneError::Parse(message) => write!(f, "couldn't parse scene: {}", message),
+            SceneError::Serialize(message) => write!(f, "couldn't serialize scene: {}", message),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material '{}'", name),
+            SceneError::UnknownTexture(name) => write!(f, "unknown texture '{}'", name),
+            SceneError::Invalid(message) => write!(f, "invalid scene: {}", message),
+            SceneError::Obj(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Obj(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(err:ObjError) -> Self {
+        SceneError::Obj(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SceneFormat {
+    Json,
+    Toml,
+    Ron,
+}
+
+impl SceneFormat {
+    pub fn from_path(path:&Path) -> Option<SceneFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(SceneFormat::Json),
+            "toml" => Some(SceneFormat::Toml),
+            "ron"  => Some(SceneFormat::Ron),
+            _ => None,
+        }
+    }
+}
+
+/// Serializable description of everything needed to render a frame.
+/// Materials are declared once by name and referenced by objects.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SceneDescription {
+    pub image: ImageDescription,
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub renderer: RendererDescription,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub textures: BTreeMap<String, TextureDescription>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    /// what rays leaving the scene see, the default sky gradient if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<EnvironmentDescription>,
+
+    /// directory that mesh paths are relative to, set by `load`
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ImageDescription {
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub origin: [f32; 3],
+    pub target: [f32; 3],
+    #[serde(default = "default_up")]
+    pub up: [f32; 3],
+    #[serde(default)]
+    pub projection: ProjectionDescription,
+    /// vertical field of view in degrees
+    #[serde(default = "default_vfov")]
+    pub vfov: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    /// distance to the plane of focus, defaults to the distance to the target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f32>,
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_vfov() -> f32 {
+    40.0
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProjectionDescription {
+    /// thin lens with the camera's field of view, aperture and focus distance
+    #[default]
+    Perspective,
+    /// parallel rays, for isometric and other technical views. The view is
+    /// `width` by `height` scene units, if only one is given the other
+    /// follows the image's aspect ratio and with neither it is as high as
+    /// the perspective view at the target. The aperture is ignored.
+    Orthographic {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        width: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        height: Option<f32>,
+    },
+    /// latitude-longitude panorama of every direction, for 2:1 images
+    Equirectangular,
+    /// the six faces of a cube side by side, for 6:1 images
+    CubeMap,
+    Fisheye {
+        /// across the image circle in degrees, up to 360
+        #[serde(default = "default_fisheye_fov")]
+        fov: f32,
+        #[serde(default)]
+        mapping: FisheyeMapping,
+    },
+}
+
+fn default_fisheye_fov() -> f32 {
+    180.0
+}
+
+impl ProjectionDescription {
+    pub fn from_name(name:&str) -> Option<ProjectionDescription> {
+        match name {
+            "perspective" => Some(ProjectionDescription::Perspective),
+            "orthographic" => Some(ProjectionDescription::Orthographic { width: None, height: None }),
+            "equirectangular" => Some(ProjectionDescription::Equirectangular),
+            "cube_map" => Some(ProjectionDescription::CubeMap),
+            "fisheye" => Some(ProjectionDescription::Fisheye { fov: default_fisheye_fov(), mapping: FisheyeMapping::default() }),
+            _ => None,
+        }
+    }
+
+    /// width over height of the images the projection is made for, if it has one
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        match self {
+            ProjectionDescription::Equirectangular => Some(2.0),
+            ProjectionDescription::CubeMap => Some(6.0),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct RendererDescription {
+    pub samples: usize,
+    pub max_depth: usize,
+    #[serde(default)]
+    pub seed: u64,
+    /// in stops
+    #[serde(default)]
+    pub exposure: f32,
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    #[serde(default)]
+    pub integrator: IntegratorDescription,
+    #[serde(default)]
+    pub sampler: SamplerType,
+    /// how samples are weighted into the pixels around them
+    #[serde(default)]
+    pub filter: FilterDescription,
+    /// runs once the image is rendered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denoiser: Option<DenoiserDescription>,
+    /// stops sampling converged pixels early, `samples` is then the most a pixel gets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<AdaptiveDescription>,
+}
+
+impl Default for RendererDescription {
+    fn default() -> Self {
+        RendererDescription {
+            samples: 32,
+            max_depth: 32,
+            seed: 0,
+            exposure: 0.0,
+            tone_mapping: ToneMapping::default(),
+            integrator: IntegratorDescription::default(),
+            sampler: SamplerType::default(),
+            filter: FilterDescription::default(),
+            denoiser: None,
+            adaptive: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IntegratorDescription {
+    #[default]
+    Path,
+    /// light straight from emitters, specular chains are still followed
+    Direct,
+    AmbientOcclusion {
+        /// occluders farther than this are ignored
+        #[serde(default = "default_ao_distance")]
+        distance: f32,
+    },
+    Debug {
+        view: DebugView,
+    },
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FilterDescription {
+    #[serde(rename = "type")]
+    pub filter: FilterType,
+    /// in pixels, each filter has a default of its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f32>,
+}
+
+impl FilterDescription {
+    pub fn build(&self) -> Arc<dyn Filter> {
+        self.filter.build(self.radius.unwrap_or(self.filter.default_radius()))
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct AdaptiveDescription {
+    #[serde(default = "default_min_samples")]
+    pub min_samples: usize,
+    /// relative error below which a pixel counts as converged
+    #[serde(default = "default_adaptive_threshold")]
+    pub threshold: f32,
+}
+
+impl Default for AdaptiveDescription {
+    fn default() -> Self {
+        AdaptiveDescription {
+            min_samples: default_min_samples(),
+            threshold: default_adaptive_threshold(),
+        }
+    }
+}
+
+fn default_min_samples() -> usize {
+    AdaptiveSampling::default().min_samples
+}
+
+fn default_adaptive_threshold() -> f32 {
+    AdaptiveSampling::default().threshold
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DenoiserDescription {
+    /// edge avoiding à-trous wavelet guided by normals, albedo and depth
+    Atrous {
+        #[serde(default = "default_atrous_iterations")]
+        iterations: usize,
+        #[serde(default)]
+        sigma_color: Option<f32>,
+        #[serde(default)]
+        sigma_normal: Option<f32>,
+        #[serde(default)]
+        sigma_albedo: Option<f32>,
+        #[serde(default)]
+        sigma_depth: Option<f32>,
+    },
+}
+
+fn default_atrous_iterations() -> usize {
+    AtrousFilter::default().iterations
+}
+
+impl DenoiserDescription {
+    /// `atrous`
+    pub fn from_name(name:&str) -> Option<DenoiserDescription> {
+        match name {
+            "atrous" => Some(DenoiserDescription::Atrous {
+                iterations: default_atrous_iterations(),
+                sigma_color: None,
+                sigma_normal: None,
+                sigma_albedo: None,
+                sigma_depth: None,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self) -> AtrousFilter {
+        match *self {
+            DenoiserDescription::Atrous { iterations, sigma_color, sigma_normal, sigma_albedo, sigma_depth } => {
+                let defaults = AtrousFilter::default();
+                AtrousFilter {
+                    iterations,
+                    sigma_color: sigma_color.unwrap_or(defaults.sigma_color),
+                    sigma_normal: sigma_normal.unwrap_or(defaults.sigma_normal),
+                    sigma_albedo: sigma_albedo.unwrap_or(defaults.sigma_albedo),
+                    sigma_depth: sigma_depth.unwrap_or(defaults.sigma_depth),
+                }
+            }
+        }
+    }
+}
+
+fn default_ao_distance() -> f32 {
+    1.0
+}
+
+impl IntegratorDescription {
+    /// `path`, `direct`, `ao` or the name of a debug view
+    pub fn from_name(name:&str) -> Option<IntegratorDescription> {
+        match name {
+            "path" => Some(IntegratorDescription::Path),
+            "direct" => Some(IntegratorDescription::Direct),
+            "ao" | "ambient_occlusion" => Some(IntegratorDescription::AmbientOcclusion { distance: default_ao_distance() }),
+            _ => DebugView::from_name(name).map(|view| IntegratorDescription::Debug { view }),
+        }
+    }
+
+    pub fn build(&self, max_depth:usize) -> Arc<dyn Integrator> {
+        match self {
+            IntegratorDescription::Path => Arc::new(PathTracer::new(max_depth)),
+            IntegratorDescription::Direct => Arc::new(DirectLighting::new(max_depth)),
+            IntegratorDescription::AmbientOcclusion { distance } => Arc::new(AmbientOcclusion::new(*distance)),
+            IntegratorDescription::Debug { view } => Arc::new(DebugIntegrator::new(*view)),
+        }
+    }
+}
+
+/// either an rgb triple or the name of a texture
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ColorOrTexture {
+    Color([f32; 3]),
+    Texture(String),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextureDescription {
+    Solid {
+        color: [f32; 3],
+    },
+    Checker {
+        scale: f32,
+        even: [f32; 3],
+        odd: [f32; 3],
+        #[serde(default = "default_checker_mapping")]
+        mapping: TextureMapping,
+    },
+    Noise {
+        kind: NoiseKind,
+        #[serde(default = "default_noise_scale")]
+        scale: f32,
+        #[serde(default = "default_noise_color")]
+        color: [f32; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+    /// bitmap or Radiance .hdr file, relative to the scene file
+    Image {
+        path: PathBuf,
+    },
+}
+
+fn default_checker_mapping() -> TextureMapping {
+    TextureMapping::Point
+}
+
+fn default_noise_scale() -> f32 {
+    1.0
+}
+
+fn default_noise_color() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: ColorOrTexture,
+    },
+    Metal {
+        albedo: ColorOrTexture,
+        #[serde(default)]
+        roughness: f32,
+    },
+    Dialectric {
+        refraction_index: f32,
+    },
+    DiffuseLight {
+        emit: ColorOrTexture,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ObjectDescription {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        material: String,
+    },
+    /// wavefront .obj file, `material` is used for faces without a `usemtl`
+    Mesh {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EnvironmentDescription {
+    Constant {
+        color: [f32; 3],
+    },
+    /// blends from `bottom` straight down to `top` straight up
+    Gradient {
+        bottom: [f32; 3],
+        top: [f32; 3],
+    },
+    /// equirectangular Radiance .hdr or bitmap, relative to the scene file
+    Image {
+        path: PathBuf,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        /// about the y axis, in degrees
+        #[serde(default)]
+        rotation: f32,
+    },
+    /// Preetham daylight with a sun disk
+    Sky {
+        /// towards the sun
+        sun_direction: [f32; 3],
+        #[serde(default = "default_turbidity")]
+        turbidity: f32,
+        #[serde(default = "default_ground_albedo")]
+        ground_albedo: [f32; 3],
+        /// in degrees, 0 leaves out the sun
+        #[serde(default = "default_sun_angular_diameter")]
+        sun_angular_diameter: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_turbidity() -> f32 {
+    SkyParameters::default().turbidity
+}
+
+fn default_ground_albedo() -> [f32; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+fn default_sun_angular_diameter() -> f32 {
+    SkyParameters::default().sun_angular_diameter
+}
+
+fn vec3(v:[f32; 3]) -> Vector3 {
+    Vector3::new(v[0], v[1], v[2])
+}
+
+fn color(c:[f32; 3]) -> Color {
+    Color::new(c[0], c[1], c[2])
+}
+
+impl EnvironmentDescription {
+    pub fn build(&self, base_dir:&Path) -> Result<Box<dyn Environment>, SceneError> {
+        let environment: Box<dyn Environment> = match self {
+            EnvironmentDescription::Constant { color: c } => ConstantEnvironment::new(color(*c)),
+            EnvironmentDescription::Gradient { bottom, top } => {
+                GradientEnvironment::new(color(*bottom), color(*top))
+            }
+            EnvironmentDescription::Image { path, intensity, rotation } => {
+                let path = base_dir.join(path);
+                let texture = ImageTexture::load(&path.to_string_lossy())
+                    .map_err(|source| SceneError::Io { path, source })?;
+                ImageEnvironment::new(texture, *intensity, *rotation)
+            }
+            EnvironmentDescription::Sky { sun_direction, turbidity, ground_albedo, sun_angular_diameter, intensity } => {
+                if vec3(*sun_direction).magnitude2() == 0.0 {
+                    return Err(SceneError::Invalid("sky sun_direction must not be zero".to_string()));
+                }
+                SkyEnvironment::new(SkyParameters {
+                    sun_direction: vec3(*sun_direction),
+                    turbidity: *turbidity,
+                    ground_albedo: color(*ground_albedo),
+                    sun_angular_diameter: *sun_angular_diameter,
+                    intensity: *intensity,
+                })
+            }
+        };
+        Ok(environment)
+    }
+}
+
+impl CameraDescription {
+    /// the camera for an image of `width` by `height` pixels
+    pub fn build(&self, width:usize, height:usize) -> Box<dyn Camera> {
+        let aspect_ratio = width as f32 / height as f32;
+        let origin = vec3(self.origin);
+        let up = vec3(self.up);
+        let target = match self.focus_distance {
+            None => vec3(self.target),
+            Some(distance) => distance * (vec3(self.target) - origin).normalize() + origin,
+        };
+        match self.projection {
+            ProjectionDescription::Perspective => {
+                Box::new(ProjectiveCamera::new(origin, target, up, self.vfov, aspect_ratio, self.aperture))
+            }
+            ProjectionDescription::Orthographic { width, height } => {
+                let (width, height) = match (width, height) {
+                    (Some(width), Some(height)) => (width, height),
+                    (Some(width), None) => (width, width / aspect_ratio),
+                    (None, Some(height)) => (height * aspect_ratio, height),
+                    (None, None) => {
+                        let distance = (vec3(self.target) - origin).magnitude();
+                        let height = 2.0 * (self.vfov.to_radians() / 2.0).tan() * distance;
+                        (height * aspect_ratio, height)
+                    }
+                };
+                Box::new(ProjectiveCamera::orthographic(origin, target, up, width, height))
+            }
+            ProjectionDescription::Equirectangular => {
+                Box::new(EquirectangularCamera::new(origin, target, up, width, height))
+            }
+            ProjectionDescription::CubeMap => Box::new(CubeMapCamera::new(origin, target, up, width, height)),
+            ProjectionDescription::Fisheye { fov, mapping } => {
+                Box::new(FisheyeCamera::new(origin, target, up, fov, mapping, width, height))
+            }
+        }
+    }
+}
+
+type TextureMap<'a> = BTreeMap<&'a str, Arc<dyn Texture>>;
+
+impl ColorOrTexture {
+    fn build(&self, textures:&TextureMap) -> Result<Arc<dyn Texture>, SceneError> {
+        match self {
+            ColorOrTexture::Color(c) => Ok(SolidColor::new(color(*c))),
+            ColorOrTexture::Texture(name) => {
+                textures.get(name.as_str()).cloned().ok_or_else(|| SceneError::UnknownTexture(name.clone()))
+            }
+        }
+    }
+}
+
+impl TextureDescription {
+    /// image paths are resolved relative to `base_dir`
+    pub fn build(&self, base_dir:&Path) -> Result<Arc<dyn Texture>, SceneError> {
+        let texture: Arc<dyn Texture> = match self {
+            TextureDescription::Solid { color: c } => SolidColor::new(color(*c)),
+            TextureDescription::Checker { scale, even, odd, mapping } => {
+                let (even, odd) = (SolidColor::new(color(*even)), SolidColor::new(color(*odd)));
+                CheckerTexture::new_textured(*scale, *mapping, even, odd)
+            }
+            TextureDescription::Noise { kind, scale, color: c, seed } => {
+                NoiseTexture::new(*kind, *scale, color(*c), *seed)
+            }
+            TextureDescription::Image { path } => {
+                let path = base_dir.join(path);
+                ImageTexture::load(&path.to_string_lossy())
+                    .map_err(|source| SceneError::Io { path, source })?
+            }
+        };
+        Ok(texture)
+    }
+}
+
+impl MaterialDescription {
+    pub fn build(&self, textures:&TextureMap) -> Result<Arc<dyn Material>, SceneError> {
+        let material: Arc<dyn Material> = match self {
+            MaterialDescription::Lambertian { albedo } => Lambertian::new_textured(albedo.build(textures)?),
+            MaterialDescription::Metal { albedo, roughness } => Metal::new_textured(albedo.build(textures)?, *roughness),
+            MaterialDescription::Dialectric { refraction_index } => Dialectric::new(*refraction_index),
+            MaterialDescription::DiffuseLight { emit } => DiffuseLight::new_textured(emit.build(textures)?),
+        };
+        Ok(material)
+    }
+}
+
+impl SceneDescription {
+
+    pub fn load<P: AsRef<Path>>(path:P) -> Result<SceneDescription, SceneError> {
+        let path = path.as_ref();
+        let format = SceneFormat::from_path(path)
+            .ok_or_else(|| SceneError::UnsupportedFormat(path.to_path_buf()))?;
+        let text = std::fs::read_to_string(path)
+            .map_err(|source| SceneError::Io { path: path.to_path_buf(), source })?;
+
+        let mut description = SceneDescription::parse(&text, format)?;
+        description.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(description)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path:P) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        let format = SceneFormat::from_path(path)
+            .ok_or_else(|| SceneError::UnsupportedFormat(path.to_path_buf()))?;
+        let text = self.serialize(format)?;
+        std::fs::write(path, text)
+            .map_err(|source| SceneError::Io { path: path.to_path_buf(), source })
+    }
+
+    pub fn parse(text:&str, format:SceneFormat) -> Result<SceneDescription, SceneError> {
+        let parse_error = |err:&dyn fmt::Display| SceneError::Parse(err.to_string());
+        match format {
+            SceneFormat::Json => serde_json::from_str(text).map_err(|e| parse_error(&e)),
+            SceneFormat::Toml => toml::from_str(text).map_err(|e| parse_error(&e)),
+            SceneFormat::Ron  => ron::from_str(text).map_err(|e| parse_error(&e)),
+        }
+    }
+
+    pub fn serialize(&self, format:SceneFormat) -> Result<String, SceneError> {
+        let serialize_error = |err:&dyn fmt::Display| SceneError::Serialize(err.to_string());
+        match format {
+            SceneFormat::Json => serde_json::to_string_pretty(self).map_err(|e| serialize_error(&e)),
+            SceneFormat::Toml => toml::to_string_pretty(self).map_err(|e| serialize_error(&e)),
+            SceneFormat::Ron  => {
+                ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                    .map_err(|e| serialize_error(&e))
+            }
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.image.width as f32 / self.image.height as f32
+    }
+
+    pub fn camera(&self) -> Box<dyn Camera> {
+        self.camera.build(self.image.width, self.image.height)
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        let renderer = Renderer::new(self.renderer.samples, self.renderer.max_depth)
+            .with_seed(self.renderer.seed)
+            .with_display_transform(DisplayTransform::new(self.renderer.exposure, self.renderer.tone_mapping))
+            .with_integrator(self.renderer.integrator.build(self.renderer.max_depth))
+            .with_sampler(self.renderer.sampler)
+            .with_filter(self.renderer.filter.build());
+        match self.renderer.adaptive {
+            Some(adaptive) => renderer.with_adaptive_sampling(AdaptiveSampling::new(adaptive.min_samples, adaptive.threshold)),
+            None => renderer,
+        }
+    }
+
+    /// builds the objects into `scene`, meshes are loaded from disk
+    pub fn populate(&self, scene:&mut Scene) -> Result<(), SceneError> {
+        let textures: TextureMap = self.textures.iter()
+            .map(|(name, texture)| Ok((name.as_str(), texture.build(&self.base_dir)?)))
+            .collect::<Result<_, SceneError>>()?;
+        let materials: BTreeMap<&str, Arc<dyn Material>> = self.materials.iter()
+            .map(|(name, material)| Ok((name.as_str(), material.build(&textures)?)))
+            .collect::<Result<_, SceneError>>()?;
+        let lookup = |name:&str| {
+            materials.get(name).cloned().ok_or_else(|| SceneError::UnknownMaterial(name.to_string()))
+        };
+
+        if let Some(environment) = &self.environment {
+            scene.set_environment(environment.build(&self.base_dir)?);
+        }
+
+        for object in &self.objects {
+            match object {
+                ObjectDescription::Sphere { center, radius, material } => {
+                    scene.push(Sphere::new(vec3(*center), *radius, lookup(material)?));
+                }
+                ObjectDescription::Triangle { vertices, material } => {
+                    let [p0, p1, p2] = vertices.map(vec3);
+                    scene.push(Triangle::new(p0, p1, p2, lookup(material)?));
+                }
+                ObjectDescription::Mesh { path, material } => {
+                    let path = self.base_dir.join(path);
+                    let model = match material {
+                        None => obj::load_obj(path)?,
+                        Some(name) => obj::load_obj_with_default(path, lookup(name)?)?,
+                    };
+                    model.push_into(scene);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// convenience for building a new scene with its BVH ready to render
+    pub fn scene(&self) -> Result<Scene, SceneError> {
+        let mut scene = Scene::new();
+        self.populate(&mut scene)?;
+        scene.build_bvh();
+        Ok(scene)
+    }
+}
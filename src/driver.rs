@@ -0,0 +1,194 @@
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::image::Region;
+use crate::camera::Camera;
+use crate::scene::Scene;
+use crate::renderer::Renderer;
+use crate::renderer::RenderTarget;
+use crate::session::RenderSession;
+
+/// Order in which tiles are handed to the worker threads, which decides
+/// where the image fills in first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileOrder {
+    /// row by row
+    #[default]
+    Scanline,
+    /// outwards from the centre, so the subject usually shows up first
+    Spiral,
+    /// along a Hilbert curve, consecutive tiles stay close together
+    Hilbert,
+}
+
+impl TileOrder {
+    pub fn from_name(name:&str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+
+    /// tiles of `tile_size` covering `full`, in this order
+    pub fn tiles(&self, full:Region, tile_size:usize) -> Vec<Region> {
+        let mut tiles = full.chunks(tile_size);
+        let columns = full.width.div_ceil(tile_size);
+        let rows = full.height.div_ceil(tile_size);
+        let grid = |tile:&Region| (tile.x / tile_size, tile.y / tile_size);
+        match self {
+            TileOrder::Scanline => (),
+            TileOrder::Spiral => {
+                let cx = (columns as f32 - 1.0) * 0.5;
+                let cy = (rows as f32 - 1.0) * 0.5;
+                // ring by ring around the centre, counter clockwise within a ring
+                let key = |tile:&Region| {
+                    let (i, j) = grid(tile);
+                    let (dx, dy) = (i as f32 - cx, j as f32 - cy);
+                    (f32::max(dx.abs(), dy.abs()), dy.atan2(dx))
+                };
+                tiles.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            }
+            TileOrder::Hilbert => {
+                let n = usize::max(columns, rows).next_power_of_two();
+                tiles.sort_by_key(|tile| {
+                    let (i, j) = grid(tile);
+                    hilbert_index(n, i, j)
+                });
+            }
+        }
+        tiles
+    }
+}
+
+/// distance along the Hilbert curve filling an `n` by `n` grid, `n` a power of two
+fn hilbert_index(n:usize, mut x:usize, mut y:usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as usize;
+        let ry = (y & s > 0) as usize;
+        d += s * s * ((3 * rx) ^ ry);
+        // rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+/// Renders a whole target in parallel, split into square tiles that a
+/// pool of threads takes in turn. Tiles are rendered into their own
+/// buffers, reaching as far past their edges as the filter does, and
+/// added to the target as they finish.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderDriver {
+    tile_size: usize,
+    order: TileOrder,
+    nthreads: usize,
+}
+
+impl Default for RenderDriver {
+    fn default() -> Self {
+        RenderDriver {
+            tile_size: 64,
+            order: TileOrder::default(),
+            nthreads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+impl RenderDriver {
+
+    /// 64 pixel tiles in scanline order, one thread per core
+    pub fn new() -> RenderDriver {
+        RenderDriver::default()
+    }
+
+    pub fn with_tile_size(mut self, tile_size:usize) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+
+    pub fn with_tile_order(mut self, order:TileOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn with_threads(mut self, nthreads:usize) -> Self {
+        self.nthreads = nthreads.max(1);
+        self
+    }
+
+    pub fn tile_size(&self) -> usize {
+        self.tile_size
+    }
+
+    pub fn threads(&self) -> usize {
+        self.nthreads
+    }
+
+    /// the tiles covering the target, in the order they are rendered
+    pub fn tiles(&self, target:&RenderTarget) -> Vec<Region> {
+        let full = Region { x: 0, y: 0, width: target.full_width, height: target.full_height };
+        self.order.tiles(full, self.tile_size)
+    }
+
+    /// takes every sample of every pixel of the target
+    pub fn render(&self, renderer:&Renderer, camera:&dyn Camera, scene:&Scene, target:&RenderTarget, session:&RenderSession) {
+        session.begin(self.tiles(target).len());
+        self.render_samples(renderer, camera, scene, target, 0..renderer.nsamples(), session);
+    }
+
+    /// renders the whole target in passes of `pass_samples` samples per
+    /// pixel. After every pass `on_pass` gets the number of samples taken so
+    /// far and the target to take a snapshot of, returning false stops the
+    /// render as does the session running out of time or being cancelled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_progressive<F>(&self, renderer:&Renderer, camera:&dyn Camera, scene:&Scene, target:&RenderTarget, session:&RenderSession, pass_samples:usize, mut on_pass:F)
+    where F: FnMut(usize, &RenderTarget) -> bool
+    {
+        let nsamples = renderer.nsamples();
+        let pass_samples = pass_samples.max(1);
+        session.begin(self.tiles(target).len() * nsamples.div_ceil(pass_samples));
+        for start in (0..nsamples).step_by(pass_samples) {
+            let end = usize::min(start + pass_samples, nsamples);
+            self.render_samples(renderer, camera, scene, target, start..end, session);
+            if !on_pass(end, target) || session.stop_reason().is_some() {
+                break;
+            }
+        }
+    }
+
+    /// takes the samples in `samples` in every tile, see `Renderer::render_samples`.
+    /// Progress is counted in the session but it isn't restarted.
+    pub fn render_samples(&self, renderer:&Renderer, camera:&dyn Camera, scene:&Scene, target:&RenderTarget, samples:Range<usize>, session:&RenderSession) {
+        let tiles = self.tiles(target);
+        let next = AtomicUsize::new(0);
+
+        std::thread::scope( |scope| {
+            for _ in 0..usize::min(self.nthreads, tiles.len()) {
+                scope.spawn( || {
+                    while let Some(region) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        // samples near the edge of a tile also land in its neighbours
+                        renderer.render_region(camera, scene, target, *region, samples.clone(), session);
+                        session.tile_done();
+                    }
+                });
+            }
+        });
+        // pixels are only final once every tile around them is done
+        renderer.develop(&target.buffer);
+    }
+}
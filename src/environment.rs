@@ -0,0 +1,217 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::image::Color;
+use crate::textures::ImageTexture;
+use crate::tonemap::luminance;
+
+use cgmath::InnerSpace;
+
+type Vector2 = cgmath::Vector2<f32>;
+type Vector3 = cgmath::Vector3<f32>;
+
+/// Radiance arriving from infinitely far away, seen by every ray
+/// that leaves the scene
+pub trait Environment: Sync+Send {
+    /// radiance arriving along the unit `direction`, pointing away from the scene
+    fn radiance(&self, direction:Vector3) -> Color;
+
+    /// environments that can be importance sampled are treated as a light
+    fn is_sampled(&self) -> bool {
+        false
+    }
+
+    /// picks a direction, returns it with its solid angle pdf
+    fn sample(&self, _u:Vector2) -> Option<(Vector3, f32)> {
+        None
+    }
+
+    /// solid angle pdf of `sample` returning `direction`
+    fn pdf(&self, _direction:Vector3) -> f32 {
+        0.0
+    }
+}
+
+/// Same radiance in every direction, black makes a studio backdrop
+pub struct ConstantEnvironment {
+    pub color:Color,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color:Color) -> Box<ConstantEnvironment> {
+        Box::new(ConstantEnvironment{color})
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _direction:Vector3) -> Color {
+        self.color
+    }
+}
+
+/// Blends from `bottom` straight down to `top` straight up
+pub struct GradientEnvironment {
+    pub bottom:Color,
+    pub top:Color,
+}
+
+impl GradientEnvironment {
+    pub fn new(bottom:Color, top:Color) -> Box<GradientEnvironment> {
+        Box::new(GradientEnvironment{bottom, top})
+    }
+
+    /// the white to blue sky scenes get when they don't ask for anything else
+    pub fn sky() -> Box<GradientEnvironment> {
+        GradientEnvironment::new(
+            Color::new(1.0, 1.0, 1.0),
+            Color::new(0.5, 0.7, 1.0),
+        )
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn radiance(&self, direction:Vector3) -> Color {
+        let t = 0.5 * (direction.y + 1.0);
+        Color::lerp(t, self.bottom, self.top)
+    }
+}
+
+/// Piecewise constant distribution over [0, 1) for inverting a sampled cdf
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func:Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            cdf.push(cdf[i] + func[i] / n as f32);
+        }
+        let integral = cdf[n];
+        match integral > 0.0 {
+            true => cdf.iter_mut().for_each(|c| *c /= integral),
+            // nothing to prefer, sample uniformly
+            false => cdf.iter_mut().enumerate().for_each(|(i, c)| *c = i as f32 / n as f32),
+        }
+        Distribution1D { func, cdf, integral }
+    }
+
+    /// returns the sampled position in [0, 1), its pdf and the bucket it fell in
+    fn sample(&self, u:f32) -> (f32, f32, usize) {
+        let n = self.func.len();
+        let i = usize::min(self.cdf.partition_point(|&c| c <= u).saturating_sub(1), n - 1);
+        let width = self.cdf[i+1] - self.cdf[i];
+        let du = match width > 0.0 {
+            true => (u - self.cdf[i]) / width,
+            false => 0.0,
+        };
+        let x = f32::min((i as f32 + du) / n as f32, 1.0 - f32::EPSILON);
+        (x, self.pdf(i), i)
+    }
+
+    #[inline]
+    fn pdf(&self, i:usize) -> f32 {
+        match self.integral > 0.0 {
+            true => self.func[i] / self.integral,
+            false => 1.0,
+        }
+    }
+}
+
+/// Equirectangular map, u follows the azimuth and v goes from straight
+/// down at 0 to straight up at 1. Seen from inside the middle of the map
+/// is along +x and its right half towards +z, as in panoramas and the
+/// images of an `EquirectangularCamera` looking along +x.
+/// Directions are importance sampled by the luminance of the map.
+pub struct ImageEnvironment {
+    texture: Arc<ImageTexture>,
+    intensity: f32,
+    /// rotation about the y axis in radians
+    rotation: f32,
+    rows: Distribution1D,
+    columns: Vec<Distribution1D>,
+}
+
+impl ImageEnvironment {
+    /// `rotation` turns the map about the y axis, in degrees
+    pub fn new(texture:Arc<ImageTexture>, intensity:f32, rotation:f32) -> Box<ImageEnvironment> {
+        let (w, h) = (texture.width(), texture.height());
+        let columns: Vec<Distribution1D> = (0..h).map( |y| {
+            // rows near the poles cover less solid angle
+            let sin_theta = (PI * (y as f32 + 0.5) / h as f32).sin();
+            let func = (0..w).map( |x| {
+                let c = texture.texel(x, y);
+                f32::max(luminance([c.red, c.green, c.blue]), 0.0) * sin_theta
+            }).collect();
+            Distribution1D::new(func)
+        }).collect();
+        let rows = Distribution1D::new(columns.iter().map(|c| c.integral).collect());
+        Box::new(ImageEnvironment {
+            texture,
+            intensity,
+            rotation: rotation.to_radians(),
+            rows,
+            columns,
+        })
+    }
+
+    fn direction_to_uv(&self, direction:Vector3) -> Vector2 {
+        let theta = f32::acos(f32::clamp(-direction.y, -1.0, 1.0));
+        let phi = f32::atan2(direction.z, direction.x) + PI + self.rotation;
+        let u = phi / (2.0 * PI);
+        Vector2::new(u - u.floor(), theta / PI)
+    }
+
+    fn texel_at(&self, uv:Vector2) -> (usize, usize) {
+        let (w, h) = (self.texture.width(), self.texture.height());
+        let x = usize::min((uv.x * w as f32) as usize, w - 1);
+        let y = usize::min((uv.y * h as f32) as usize, h - 1);
+        (x, y)
+    }
+
+    fn uv_to_direction(&self, uv:Vector2) -> Vector3 {
+        let phi = 2.0 * PI * uv.x - PI - self.rotation;
+        let theta = PI * uv.y;
+        let sin_theta = theta.sin();
+        Vector3::new(sin_theta * phi.cos(), -theta.cos(), sin_theta * phi.sin())
+    }
+}
+
+impl Environment for ImageEnvironment {
+    fn radiance(&self, direction:Vector3) -> Color {
+        // unfiltered so the radiance is constant over each texel like the pdf,
+        // filtering would spread bright spots into texels that are rarely sampled
+        let (x, y) = self.texel_at(self.direction_to_uv(direction));
+        self.intensity * self.texture.texel(x, y)
+    }
+
+    fn is_sampled(&self) -> bool {
+        self.rows.integral > 0.0
+    }
+
+    fn sample(&self, u:Vector2) -> Option<(Vector3, f32)> {
+        let (v, row_pdf, y) = self.rows.sample(u.y);
+        let (u, column_pdf, _) = self.columns[y].sample(u.x);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        // the map covers 2pi by pi radians
+        let pdf = row_pdf * column_pdf / (2.0 * PI * PI * sin_theta);
+        Some((self.uv_to_direction(Vector2::new(u, v)), pdf))
+    }
+
+    fn pdf(&self, direction:Vector3) -> f32 {
+        let uv = self.direction_to_uv(direction.normalize());
+        let sin_theta = (PI * uv.y).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.texel_at(uv);
+        self.rows.pdf(y) * self.columns[y].pdf(x) / (2.0 * PI * PI * sin_theta)
+    }
+}
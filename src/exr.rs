@@ -0,0 +1,126 @@
+// single part scanline OpenEXR writer with zip compression
+
+use half::f16;
+
+use crate::deflate;
+use crate::image::ExrPixelType;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+
+const ZIP_COMPRESSION: u8 = 3;
+const ZIP_LINES_PER_BLOCK: usize = 16;
+const INCREASING_Y: u8 = 0;
+
+/// One channel of an image, values are in top down rows
+pub struct Channel {
+    pub name: String,
+    pub pixel_type: ExrPixelType,
+    pub values: Vec<f32>,
+}
+
+fn write_attribute(out:&mut Vec<u8>, name:&str, kind:&str, value:&[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+fn box2i(width:usize, height:usize) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn header(width:usize, height:usize, channels:&[Channel]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for channel in channels {
+        let pixel_type = match channel.pixel_type {
+            ExrPixelType::Half => 1i32,
+            ExrPixelType::Float => 2i32,
+        };
+        list.extend_from_slice(channel.name.as_bytes());
+        list.push(0);
+        list.extend_from_slice(&pixel_type.to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]); // perceptually linear flag and padding
+        list.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        list.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    list.push(0);
+
+    let mut out = Vec::new();
+    write_attribute(&mut out, "channels", "chlist", &list);
+    write_attribute(&mut out, "compression", "compression", &[ZIP_COMPRESSION]);
+    write_attribute(&mut out, "dataWindow", "box2i", &box2i(width, height));
+    write_attribute(&mut out, "displayWindow", "box2i", &box2i(width, height));
+    write_attribute(&mut out, "lineOrder", "lineOrder", &[INCREASING_Y]);
+    write_attribute(&mut out, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    write_attribute(&mut out, "screenWindowCenter", "v2f", &[0u8; 8]);
+    write_attribute(&mut out, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    out.push(0);
+    out
+}
+
+/// zip blocks deinterleave the bytes and delta encode them before deflating,
+/// blocks that don't shrink are stored as they are
+fn compress_block(raw:&[u8]) -> Vec<u8> {
+    let mut reordered = Vec::with_capacity(raw.len());
+    reordered.extend(raw.iter().step_by(2));
+    reordered.extend(raw.iter().skip(1).step_by(2));
+
+    let mut previous = reordered.first().copied().unwrap_or(0);
+    for byte in reordered.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+
+    let compressed = deflate::zlib_compress(&reordered);
+    match compressed.len() < raw.len() {
+        true => compressed,
+        false => raw.to_vec(),
+    }
+}
+
+/// encodes any number of channels, layers are named like `layer.channel`
+pub fn encode(width:usize, height:usize, mut channels:Vec<Channel>) -> Vec<u8> {
+    assert!(channels.iter().all(|c| c.values.len() == width * height), "exr channel data doesn't match the image size");
+    // channels are stored in alphabetical order
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&header(width, height, &channels));
+
+    // offsets are filled in once the blocks are written
+    let nblocks = height.div_ceil(ZIP_LINES_PER_BLOCK);
+    let table = out.len();
+    out.resize(table + 8*nblocks, 0);
+
+    let mut raw = Vec::new();
+    for block in 0..nblocks {
+        let y0 = block * ZIP_LINES_PER_BLOCK;
+        let y1 = usize::min(y0 + ZIP_LINES_PER_BLOCK, height);
+
+        // every scanline holds each channel's values in turn
+        raw.clear();
+        for y in y0..y1 {
+            for channel in &channels {
+                for &value in &channel.values[y*width..(y+1)*width] {
+                    match channel.pixel_type {
+                        ExrPixelType::Half => raw.extend_from_slice(&f16::from_f32(value).to_le_bytes()),
+                        ExrPixelType::Float => raw.extend_from_slice(&value.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        let data = compress_block(&raw);
+
+        let offset = out.len() as u64;
+        out[table + 8*block..table + 8*block + 8].copy_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(y0 as i32).to_le_bytes());
+        out.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        out.extend_from_slice(&data);
+    }
+    out
+}
@@ -0,0 +1,249 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Pixel reconstruction filter. Every camera sample is splatted into the
+/// pixels within `radius` of where it was taken, weighted by the filter,
+/// and a pixel is the weighted average of what landed on it. Offsets are
+/// in pixels from the pixel centre.
+pub trait Filter: Send + Sync {
+    /// how far a sample reaches, in pixels
+    fn radius(&self) -> f32;
+
+    /// weight of a sample `x`, `y` pixels away, may be negative
+    fn evaluate(&self, x:f32, y:f32) -> f32;
+}
+
+/// Which filter the renderer reconstructs pixels with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterType {
+    /// the same weight everywhere, with the default radius samples stay in their pixel
+    #[default]
+    Box,
+    /// weight falling linearly to zero at the radius
+    Tent,
+    /// soft, blurs a little but never rings
+    Gaussian,
+    /// Mitchell–Netravali cubic with B = C = 1/3, sharper than a Gaussian with little ringing
+    Mitchell,
+    /// windowed sinc, the sharpest, rings the most around hard edges. Needs
+    /// a few samples per pixel, with one the weights in a pixel may cancel out
+    Lanczos,
+}
+
+impl FilterType {
+    pub fn from_name(name:&str) -> Option<FilterType> {
+        match name {
+            "box" => Some(FilterType::Box),
+            "tent" => Some(FilterType::Tent),
+            "gaussian" => Some(FilterType::Gaussian),
+            "mitchell" => Some(FilterType::Mitchell),
+            "lanczos" => Some(FilterType::Lanczos),
+            _ => None,
+        }
+    }
+
+    /// radius in pixels used when none is given
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.0,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell => 2.0,
+            FilterType::Lanczos => 3.0,
+        }
+    }
+
+    pub fn build(&self, radius:f32) -> Arc<dyn Filter> {
+        match self {
+            FilterType::Box => Arc::new(BoxFilter::new(radius)),
+            FilterType::Tent => Arc::new(TentFilter::new(radius)),
+            FilterType::Gaussian => Arc::new(GaussianFilter::new(radius)),
+            FilterType::Mitchell => Arc::new(MitchellFilter::new(radius)),
+            FilterType::Lanczos => Arc::new(LanczosFilter::new(radius)),
+        }
+    }
+}
+
+pub struct BoxFilter {
+    radius: f32,
+}
+
+impl BoxFilter {
+    pub fn new(radius:f32) -> BoxFilter {
+        BoxFilter { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x:f32, y:f32) -> f32 {
+        match x.abs() <= self.radius && y.abs() <= self.radius {
+            true => 1.0,
+            false => 0.0,
+        }
+    }
+}
+
+pub struct TentFilter {
+    radius: f32,
+}
+
+impl TentFilter {
+    pub fn new(radius:f32) -> TentFilter {
+        TentFilter { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x:f32, y:f32) -> f32 {
+        f32::max(0.0, self.radius - x.abs()) * f32::max(0.0, self.radius - y.abs())
+    }
+}
+
+/// Gaussian with a standard deviation of a third of the radius, shifted
+/// down so it reaches zero at the radius instead of being cut off
+pub struct GaussianFilter {
+    radius: f32,
+    sigma: f32,
+}
+
+impl GaussianFilter {
+    pub fn new(radius:f32) -> GaussianFilter {
+        GaussianFilter { radius, sigma: radius / 3.0 }
+    }
+
+    fn gaussian(&self, x:f32) -> f32 {
+        let g = |x:f32| (-x * x / (2.0 * self.sigma * self.sigma)).exp();
+        f32::max(0.0, g(x) - g(self.radius))
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x:f32, y:f32) -> f32 {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+/// Mitchell–Netravali cubic, stretched so its support of two pixels covers the radius
+pub struct MitchellFilter {
+    radius: f32,
+    b: f32,
+    c: f32,
+}
+
+impl MitchellFilter {
+    /// with B = C = 1/3, the trade-off between blur and ringing the paper recommends
+    pub fn new(radius:f32) -> MitchellFilter {
+        MitchellFilter::with_parameters(radius, 1.0 / 3.0, 1.0 / 3.0)
+    }
+
+    pub fn with_parameters(radius:f32, b:f32, c:f32) -> MitchellFilter {
+        MitchellFilter { radius, b, c }
+    }
+
+    fn mitchell(&self, x:f32) -> f32 {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x / self.radius).abs();
+        let weight = match x {
+            x if x < 1.0 => (12.0 - 9.0*b - 6.0*c) * x*x*x + (-18.0 + 12.0*b + 6.0*c) * x*x + (6.0 - 2.0*b),
+            x if x < 2.0 => (-b - 6.0*c) * x*x*x + (6.0*b + 30.0*c) * x*x + (-12.0*b - 48.0*c) * x + (8.0*b + 24.0*c),
+            _ => 0.0,
+        };
+        weight / 6.0
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x:f32, y:f32) -> f32 {
+        self.mitchell(x) * self.mitchell(y)
+    }
+}
+
+/// sinc windowed by a sinc stretched to the radius, the radius in pixels is
+/// the number of lobes on each side
+pub struct LanczosFilter {
+    radius: f32,
+}
+
+impl LanczosFilter {
+    pub fn new(radius:f32) -> LanczosFilter {
+        LanczosFilter { radius }
+    }
+
+    fn lanczos(&self, x:f32) -> f32 {
+        match x.abs() < self.radius {
+            true => sinc(x) * sinc(x / self.radius),
+            false => 0.0,
+        }
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x:f32, y:f32) -> f32 {
+        self.lanczos(x) * self.lanczos(y)
+    }
+}
+
+fn sinc(x:f32) -> f32 {
+    match x.abs() < 1e-5 {
+        true => 1.0,
+        false => (PI * x).sin() / (PI * x),
+    }
+}
+
+/// Weights of a filter looked up from a table over one quadrant rather
+/// than evaluated for every pixel a sample lands in, filters are symmetric
+pub struct FilterTable {
+    radius: f32,
+    weights: Vec<f32>,
+}
+
+impl FilterTable {
+    const SIZE: usize = 64;
+
+    pub fn new(filter:&dyn Filter) -> FilterTable {
+        let radius = filter.radius();
+        let n = FilterTable::SIZE;
+        // sampled at the centre of every cell
+        let at = |i:usize| (i as f32 + 0.5) / n as f32 * radius;
+        let weights = (0..n*n).map(|i| filter.evaluate(at(i % n), at(i / n))).collect();
+        FilterTable { radius, weights }
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn evaluate(&self, x:f32, y:f32) -> f32 {
+        let (x, y) = (x.abs(), y.abs());
+        if x > self.radius || y > self.radius {
+            return 0.0;
+        }
+        let n = FilterTable::SIZE;
+        let cell = |x:f32| usize::min((x / self.radius * n as f32) as usize, n - 1);
+        self.weights[cell(x) + cell(y) * n]
+    }
+}
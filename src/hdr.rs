@@ -0,0 +1,184 @@
+// Radiance rgbe writer using the adaptive run length encoding
+
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = 127;
+const MAX_LITERALS: usize = 128;
+
+/// shared exponent encoding, the largest component keeps 8 bits of mantissa
+fn rgbe(pixel:&[f32; 4]) -> [u8; 4] {
+    let [r, g, b, _] = pixel.map(|c| match c.is_finite() { true => c.max(0.0), false => 0.0 });
+    let v = r.max(g).max(b);
+    if v < 1e-32 {
+        return [0; 4];
+    }
+    // frexp, v = m * 2^e with m in [0.5, 1)
+    let e = ((v.to_bits() >> 23) & 0xff) as i32 - 126;
+    let scale = 256.0 * (2.0f32).powi(-e);
+    [(r * scale) as u8, (g * scale) as u8, (b * scale) as u8, (e + 128) as u8]
+}
+
+/// runs of identical bytes are sent as a count above 128 followed by the
+/// byte, everything else as a count of up to 128 literal bytes
+fn encode_component(out:&mut Vec<u8>, bytes:&[u8]) {
+    let mut i = 0;
+    while i < bytes.len() {
+        // find the next run long enough to be worth it
+        let mut run_start = i;
+        let mut run_length = 0;
+        while run_start < bytes.len() {
+            run_length = bytes[run_start..].iter()
+                .take(MAX_RUN)
+                .take_while(|&&b| b == bytes[run_start])
+                .count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+        if run_length < MIN_RUN {
+            run_start = bytes.len();
+        }
+
+        while i < run_start {
+            let n = usize::min(MAX_LITERALS, run_start - i);
+            out.push(n as u8);
+            out.extend_from_slice(&bytes[i..i+n]);
+            i += n;
+        }
+        if run_start < bytes.len() {
+            out.push((128 + run_length) as u8);
+            out.push(bytes[run_start]);
+            i = run_start + run_length;
+        }
+    }
+}
+
+/// encodes top down rgba rows, alpha is dropped
+pub fn encode(width:usize, height:usize, pixels:&[[f32; 4]]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height, "hdr pixel data doesn't match the image size");
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n");
+    out.extend_from_slice(format!("-Y {} +X {}\n", height, width).as_bytes());
+
+    let rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width);
+    let mut component = Vec::with_capacity(width);
+    for row in pixels.chunks(width) {
+        let encoded: Vec<[u8; 4]> = row.iter().map(rgbe).collect();
+        if !rle {
+            out.extend(encoded.iter().flatten());
+            continue;
+        }
+        // each scanline starts with a marker and its width, then
+        // every component is run length encoded separately
+        out.extend_from_slice(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+        for c in 0..4 {
+            component.clear();
+            component.extend(encoded.iter().map(|e| e[c]));
+            encode_component(&mut out, &component);
+        }
+    }
+    out
+}
+
+/// decodes a Radiance rgbe image into top down rgb rows, only the
+/// standard `-Y h +X w` orientation is supported
+pub fn decode(bytes:&[u8]) -> Result<(usize, usize, Vec<[f32; 3]>), String> {
+    let mut lines = bytes.split(|&b| b == b'\n');
+    let mut offset = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        offset += line.len() + 1;
+        Some(String::from_utf8_lossy(line).into_owned())
+    };
+
+    match next_line() {
+        Some(magic) if magic.starts_with("#?") => (),
+        _ => return Err("not a Radiance hdr image".to_string()),
+    }
+    // header variables end with an empty line
+    loop {
+        match next_line() {
+            None => return Err("truncated header".to_string()),
+            Some(line) if line.is_empty() => break,
+            Some(line) if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" => {
+                return Err(format!("unsupported {}", line));
+            }
+            Some(_) => (),
+        }
+    }
+    let resolution = next_line().ok_or("missing resolution")?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| "bad height")?,
+            w.parse::<usize>().map_err(|_| "bad width")?,
+        ),
+        _ => return Err(format!("unsupported orientation '{}'", resolution)),
+    };
+
+    let mut data = &bytes[offset.min(bytes.len())..];
+    let truncated = || "truncated pixel data".to_string();
+    let mut pixels = Vec::with_capacity(width*height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        let rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width)
+            && data.len() >= 4 && data[0] == 2 && data[1] == 2 && data[2] & 0x80 == 0;
+        match rle {
+            true => {
+                if (data[2] as usize) << 8 | data[3] as usize != width {
+                    return Err("scanline width mismatch".to_string());
+                }
+                data = &data[4..];
+                for c in 0..4 {
+                    let mut x = 0;
+                    while x < width {
+                        let (&count, rest) = data.split_first().ok_or_else(truncated)?;
+                        data = rest;
+                        match count > 128 {
+                            true => {
+                                let n = (count - 128) as usize;
+                                let (&value, rest) = data.split_first().ok_or_else(truncated)?;
+                                data = rest;
+                                if x + n > width {
+                                    return Err("run overflows the scanline".to_string());
+                                }
+                                scanline[x..x+n].iter_mut().for_each(|p| p[c] = value);
+                                x += n;
+                            }
+                            false => {
+                                let n = count as usize;
+                                if n == 0 || x + n > width || data.len() < n {
+                                    return Err("bad literal run".to_string());
+                                }
+                                for (p, &value) in scanline[x..x+n].iter_mut().zip(&data[..n]) {
+                                    p[c] = value;
+                                }
+                                data = &data[n..];
+                                x += n;
+                            }
+                        }
+                    }
+                }
+            }
+            false => {
+                if data.len() < 4*width {
+                    return Err(truncated());
+                }
+                for (p, chunk) in scanline.iter_mut().zip(data.chunks_exact(4)) {
+                    p.copy_from_slice(chunk);
+                }
+                data = &data[4*width..];
+            }
+        }
+        pixels.extend(scanline.iter().map( |&[r, g, b, e]| match e {
+            0 => [0.0; 3],
+            _ => {
+                let scale = (2.0f32).powi(e as i32 - 136);
+                [(r as f32 + 0.5) * scale, (g as f32 + 0.5) * scale, (b as f32 + 0.5) * scale]
+            }
+        }));
+    }
+    Ok((width, height, pixels))
+}
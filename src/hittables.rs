@@ -1,21 +1,175 @@
 
 use std::sync::Arc;
+use std::f32::consts::PI;
+use cgmath::InnerSpace;
 
 use crate::ray::Ray;
 use crate::materials::Material;
 
+type Vector2 = cgmath::Vector2<f32>;
 type Vector3 = cgmath::Vector3<f32>;
 
 pub trait Hittable: Sync+Send {
     fn hit(&self, ray:&Ray, tmin:f32, tmax:f32) -> Option<HitRecord>;
+    fn bounding_box(&self) -> Aabb;
+
+    /// emissive hittables are registered as lights by the scene
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// the material every hit reports, None if it varies or there's none
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        None
+    }
+
+    /// picks a point on the surface as seen from `origin`, `u` is a pair
+    /// of uniform random numbers. Shapes that can't be sampled return None.
+    fn sample(&self, _origin:Vector3, _u:Vector2) -> Option<ShapeSample> {
+        None
+    }
+
+    /// solid angle pdf of `sample` returning the point of `hit` when sampled from `origin`
+    fn pdf(&self, _origin:Vector3, _hit:&HitRecord) -> f32 {
+        0.0
+    }
+}
+
+pub struct ShapeSample {
+    pub point: Vector3,
+    /// outward facing surface normal at the point
+    pub normal: Vector3,
+    /// with respect to solid angle at the origin the sample was taken from
+    pub pdf: f32,
+}
+
+/// converts a pdf over surface area to one over solid angle at `origin`
+#[inline]
+fn area_to_solid_angle(area_pdf:f32, origin:Vector3, point:Vector3, normal:Vector3) -> f32 {
+    let to_point = point - origin;
+    let dist2 = to_point.magnitude2();
+    let cosine = cgmath::dot(normal, to_point).abs() / dist2.sqrt();
+    match cosine > 0.0 {
+        true => area_pdf * dist2 / cosine,
+        false => 0.0,
+    }
+}
+
+/// any two unit vectors that are perpendicular to `n` and each other
+pub(crate) fn orthonormal_basis(n:Vector3) -> (Vector3, Vector3) {
+    let a = match n.x.abs() > 0.9 {
+        true => Vector3::new(0.0, 1.0, 0.0),
+        false => Vector3::new(1.0, 0.0, 0.0),
+    };
+    let s = n.cross(a).normalize();
+    let t = n.cross(s);
+    (s, t)
+}
+
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl Aabb {
+
+    pub fn new(min:Vector3, max:Vector3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// an inverted box that any call to grow or union will replace
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other:&Aabb) -> Aabb {
+        Aabb {
+            min: Vector3::new(
+                f32::min(self.min.x, other.min.x),
+                f32::min(self.min.y, other.min.y),
+                f32::min(self.min.z, other.min.z),
+            ),
+            max: Vector3::new(
+                f32::max(self.max.x, other.max.x),
+                f32::max(self.max.y, other.max.y),
+                f32::max(self.max.z, other.max.z),
+            ),
+        }
+    }
+
+    pub fn grow(&self, point:Vector3) -> Aabb {
+        self.union(&Aabb::new(point, point))
+    }
+
+    #[inline]
+    pub fn centroid(&self) -> Vector3 {
+        0.5 * (self.min + self.max)
+    }
+
+    #[inline]
+    pub fn extent(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.extent();
+        if e.x < 0.0 || e.y < 0.0 || e.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (e.x*e.y + e.y*e.z + e.z*e.x)
+    }
+
+    pub fn largest_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x > e.y && e.x > e.z {
+            0
+        } else if e.y > e.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// slab test, inv_dir is the component-wise reciprocal of the ray direction
+    #[inline]
+    pub fn hit(&self, origin:Vector3, inv_dir:Vector3, tmin:f32, tmax:f32) -> bool {
+        let mut tmin = tmin;
+        let mut tmax = tmax;
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            let (t0, t1) = match inv_dir[axis] < 0.0 {
+                true => (t1, t0),
+                false => (t0, t1),
+            };
+            tmin = f32::max(t0, tmin);
+            tmax = f32::min(t1, tmax);
+            if tmax < tmin {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 pub struct HitRecord {
     pub t: f32,
     pub point: Vector3,
+    /// geometric normal, always facing against the ray
     pub normal: Vector3,
+    /// interpolated normal used for shading, on the same side as `normal`
+    pub shading_normal: Vector3,
+    pub uv: Vector2,
     pub front_face: bool,
     pub material: Arc<dyn Material>,
+    /// index into the scene's lights if an emissive hittable was hit
+    pub light: Option<usize>,
+    /// index of the hit object in the order objects were pushed into the scene
+    pub object: Option<usize>,
 }
 
 impl HitRecord {
@@ -25,8 +179,12 @@ impl HitRecord {
             t,
             point,
             normal,
+            shading_normal: normal,
+            uv: Vector2::new(0.0, 0.0),
             material,
             front_face: false,
+            light: None,
+            object: None,
         };
         rec.set_face_normal(ray, normal);
         rec
@@ -38,6 +196,15 @@ impl HitRecord {
             true => outward_normal,
             false => -outward_normal,
         };
+        self.shading_normal = self.normal;
+    }
+
+    /// takes an outward facing shading normal and flips it to match the geometric normal
+    pub fn set_shading_normal(&mut self, outward_normal:Vector3) {
+        self.shading_normal = match self.front_face {
+            true => outward_normal,
+            false => -outward_normal,
+        };
     }
 
 }
@@ -57,6 +224,20 @@ impl Hittable for Vec<Box<dyn Hittable>> {
         }
         result
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.iter().fold(Aabb::empty(), |bbox, hittable| {
+            bbox.union(&hittable.bounding_box())
+        })
+    }
+}
+
+/// longitude and latitude of a point on the unit sphere mapped to [0,1],
+/// v runs from the bottom pole to the top and u starts at -x going around via +z
+fn sphere_uv(p:Vector3) -> Vector2 {
+    let theta = f32::acos(f32::clamp(-p.y, -1.0, 1.0));
+    let phi = f32::atan2(-p.z, p.x) + PI;
+    Vector2::new(phi / (2.0 * PI), theta / PI)
 }
 
 pub struct Sphere {
@@ -101,10 +282,632 @@ impl Hittable for Sphere {
         };
         let point = ray.at(t);
         let normal = (point - self.origin) / self.radius;
-        Some(HitRecord::new(t, point, normal, ray, self.material.clone()))
+        let mut rec = HitRecord::new(t, point, normal, ray, self.material.clone());
+        rec.uv = sphere_uv(normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.origin - r, self.origin + r)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.material)
+    }
+
+    fn sample(&self, origin:Vector3, u:Vector2) -> Option<ShapeSample> {
+        let to_center = self.origin - origin;
+        let dist2 = to_center.magnitude2();
+        let r2 = self.radius * self.radius;
+
+        if dist2 <= r2 {
+            // inside the sphere everything is visible, sample the area uniformly
+            let z = 1.0 - 2.0 * u.x;
+            let r = f32::max(0.0, 1.0 - z*z).sqrt();
+            let phi = 2.0 * PI * u.y;
+            let normal = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+            let point = self.origin + self.radius * normal;
+            let area_pdf = 1.0 / (4.0 * PI * r2);
+            let pdf = area_to_solid_angle(area_pdf, origin, point, normal);
+            return Some(ShapeSample { point, normal, pdf });
+        }
+
+        // outside, sample directions uniformly within the cone the sphere subtends
+        let dist = dist2.sqrt();
+        let axis = to_center / dist;
+        let cos_max = f32::max(0.0, 1.0 - r2 / dist2).sqrt();
+        let cos_theta = 1.0 - u.x * (1.0 - cos_max);
+        let sin_theta = f32::max(0.0, 1.0 - cos_theta*cos_theta).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let (s, t) = orthonormal_basis(axis);
+        let direction = sin_theta * phi.cos() * s + sin_theta * phi.sin() * t + cos_theta * axis;
+
+        // nearest intersection along the direction, clamped for grazing samples
+        let b = cgmath::dot(direction, to_center);
+        let disc = f32::max(0.0, r2 - (dist2 - b*b));
+        let point = origin + (b - disc.sqrt()) * direction;
+        let normal = (point - self.origin).normalize();
+        let pdf = 1.0 / (2.0 * PI * (1.0 - cos_max));
+        Some(ShapeSample { point, normal, pdf })
+    }
+
+    fn pdf(&self, origin:Vector3, hit:&HitRecord) -> f32 {
+        let dist2 = (self.origin - origin).magnitude2();
+        let r2 = self.radius * self.radius;
+        if dist2 <= r2 {
+            let normal = (hit.point - self.origin) / self.radius;
+            return area_to_solid_angle(1.0 / (4.0 * PI * r2), origin, hit.point, normal);
+        }
+        let cos_max = f32::max(0.0, 1.0 - r2 / dist2).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_max))
     }
 }
 
 fn in_range(v:f32, min:f32, max:f32) -> bool {
     min <= v && v <= max
 }
+
+/// Möller–Trumbore ray/triangle intersection, returns (t, b1, b2) where
+/// b1 and b2 are the barycentric weights of the second and third vertex
+#[inline]
+fn intersect_triangle(ray:&Ray, p0:Vector3, p1:Vector3, p2:Vector3, tmin:f32, tmax:f32) -> Option<(f32, f32, f32)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let pvec = ray.direction().cross(e2);
+    let det = cgmath::dot(e1, pvec);
+
+    // scale the parallel test by the edge lengths so that
+    // tiny and huge triangles are treated the same
+    let epsilon = 1e-7 * e1.magnitude() * e2.magnitude();
+    if det.abs() <= epsilon {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = ray.origin() - p0;
+    let b1 = cgmath::dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = tvec.cross(e1);
+    let b2 = cgmath::dot(ray.direction(), qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = cgmath::dot(e2, qvec) * inv_det;
+    match in_range(t, tmin, tmax) {
+        true => Some((t, b1, b2)),
+        false => None,
+    }
+}
+
+#[inline]
+fn barycentric<T>(b1:f32, b2:f32, a0:T, a1:T, a2:T) -> T
+    where T: std::ops::Mul<f32, Output=T> + std::ops::Add<Output=T> {
+    a0*(1.0-b1-b2) + a1*b1 + a2*b2
+}
+
+fn triangle_bounding_box(p0:Vector3, p1:Vector3, p2:Vector3) -> Aabb {
+    // pad so that axis aligned triangles don't produce flat boxes
+    let pad = Vector3::new(1e-4, 1e-4, 1e-4);
+    let bbox = Aabb::new(p0, p0).grow(p1).grow(p2);
+    Aabb::new(bbox.min - pad, bbox.max + pad)
+}
+
+/// uniformly distributed barycentric weights (b1, b2) of the second and third vertex
+#[inline]
+fn sample_triangle(u:Vector2) -> (f32, f32) {
+    let su0 = u.x.sqrt();
+    (u.y * su0, 1.0 - su0)
+}
+
+#[inline]
+fn triangle_area(p0:Vector3, p1:Vector3, p2:Vector3) -> f32 {
+    0.5 * (p1-p0).cross(p2-p0).magnitude()
+}
+
+const DEFAULT_TRIANGLE_UVS: [Vector2; 3] = [
+    Vector2::new(0.0, 0.0),
+    Vector2::new(1.0, 0.0),
+    Vector2::new(0.0, 1.0),
+];
+
+pub struct Triangle {
+    pub material: Arc<dyn Material>,
+    pub positions: [Vector3; 3],
+    /// per-vertex normals, the face normal is used when None
+    pub normals: Option<[Vector3; 3]>,
+    pub uvs: [Vector2; 3],
+}
+
+impl Triangle {
+    pub fn new(p0:Vector3, p1:Vector3, p2:Vector3, material:Arc<dyn Material>) -> Box<Triangle> {
+        Box::new( Triangle{
+            material,
+            positions: [p0, p1, p2],
+            normals: None,
+            uvs: DEFAULT_TRIANGLE_UVS,
+        })
+    }
+
+    pub fn new_with_attributes(positions:[Vector3; 3], normals:Option<[Vector3; 3]>, uvs:Option<[Vector2; 3]>, material:Arc<dyn Material>) -> Box<Triangle> {
+        Box::new( Triangle{
+            material,
+            positions,
+            normals,
+            uvs: uvs.unwrap_or(DEFAULT_TRIANGLE_UVS),
+        })
+    }
+}
+
+impl Hittable for Triangle {
+
+    fn hit(&self, ray:&Ray, tmin:f32, tmax:f32) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.positions;
+        let (t, b1, b2) = intersect_triangle(ray, p0, p1, p2, tmin, tmax)?;
+
+        let normal = (p1-p0).cross(p2-p0).normalize();
+        let mut rec = HitRecord::new(t, ray.at(t), normal, ray, self.material.clone());
+        if let Some([n0, n1, n2]) = self.normals {
+            rec.set_shading_normal(barycentric(b1, b2, n0, n1, n2).normalize());
+        }
+        let [uv0, uv1, uv2] = self.uvs;
+        rec.uv = barycentric(b1, b2, uv0, uv1, uv2);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [p0, p1, p2] = self.positions;
+        triangle_bounding_box(p0, p1, p2)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.material)
+    }
+
+    fn sample(&self, origin:Vector3, u:Vector2) -> Option<ShapeSample> {
+        let [p0, p1, p2] = self.positions;
+        let area = triangle_area(p0, p1, p2);
+        if area <= 0.0 {
+            return None;
+        }
+        let (b1, b2) = sample_triangle(u);
+        let point = barycentric(b1, b2, p0, p1, p2);
+        let normal = (p1-p0).cross(p2-p0).normalize();
+        let pdf = area_to_solid_angle(1.0 / area, origin, point, normal);
+        Some(ShapeSample { point, normal, pdf })
+    }
+
+    fn pdf(&self, origin:Vector3, hit:&HitRecord) -> f32 {
+        let [p0, p1, p2] = self.positions;
+        area_to_solid_angle(1.0 / triangle_area(p0, p1, p2), origin, hit.point, hit.normal)
+    }
+}
+
+struct MeshData {
+    material: Arc<dyn Material>,
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    uvs: Vec<Vector2>,
+    indices: Vec<[usize; 3]>,
+}
+
+/// one face of a mesh, referencing the shared vertex buffers
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+}
+
+impl MeshData {
+    #[inline]
+    fn face_positions(&self, face:usize) -> (Vector3, Vector3, Vector3) {
+        let [i0, i1, i2] = self.indices[face];
+        let p = &self.positions;
+        (p[i0], p[i1], p[i2])
+    }
+}
+
+impl MeshTriangle {
+    #[inline]
+    fn positions(&self) -> (Vector3, Vector3, Vector3) {
+        self.mesh.face_positions(self.face)
+    }
+}
+
+impl Hittable for MeshTriangle {
+
+    fn hit(&self, ray:&Ray, tmin:f32, tmax:f32) -> Option<HitRecord> {
+        let (p0, p1, p2) = self.positions();
+        let (t, b1, b2) = intersect_triangle(ray, p0, p1, p2, tmin, tmax)?;
+
+        let mesh = &self.mesh;
+        let [i0, i1, i2] = mesh.indices[self.face];
+        let normal = (p1-p0).cross(p2-p0).normalize();
+        let mut rec = HitRecord::new(t, ray.at(t), normal, ray, mesh.material.clone());
+        if !mesh.normals.is_empty() {
+            let n = &mesh.normals;
+            rec.set_shading_normal(barycentric(b1, b2, n[i0], n[i1], n[i2]).normalize());
+        }
+        rec.uv = match mesh.uvs.is_empty() {
+            true => barycentric(b1, b2, DEFAULT_TRIANGLE_UVS[0], DEFAULT_TRIANGLE_UVS[1], DEFAULT_TRIANGLE_UVS[2]),
+            false => barycentric(b1, b2, mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]),
+        };
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let (p0, p1, p2) = self.positions();
+        triangle_bounding_box(p0, p1, p2)
+    }
+}
+
+/// Indexed triangle mesh, all faces share the vertex buffers and material.
+/// The faces are held in their own BVH so a mesh can be pushed into a
+/// `Scene` as a single hittable.
+pub struct TriangleMesh {
+    bvh: Bvh,
+    mesh: Arc<MeshData>,
+    // running total of face areas, used to pick faces when sampled as a light
+    area_cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+
+    /// `normals` and `uvs` are either empty or one per position,
+    /// each entry of `indices` is a face referencing three vertices
+    pub fn new(positions:Vec<Vector3>,
+               normals:Vec<Vector3>,
+               uvs:Vec<Vector2>,
+               indices:Vec<[usize; 3]>,
+               material:Arc<dyn Material>) -> Box<TriangleMesh> {
+
+        assert!(normals.is_empty() || normals.len() == positions.len(), "mesh needs one normal per vertex");
+        assert!(uvs.is_empty() || uvs.len() == positions.len(), "mesh needs one uv per vertex");
+        assert!(indices.iter().flatten().all(|&i| i < positions.len()), "mesh index out of bounds");
+
+        let nfaces = indices.len();
+        let mesh = Arc::new(MeshData { material, positions, normals, uvs, indices });
+        let faces = (0..nfaces).map( |face| {
+            Box::new(MeshTriangle { mesh: mesh.clone(), face }) as Box<dyn Hittable>
+        }).collect();
+
+        let mut total = 0.0;
+        let area_cdf = (0..nfaces).map( |face| {
+            let (p0, p1, p2) = mesh.face_positions(face);
+            total += triangle_area(p0, p1, p2);
+            total
+        }).collect();
+
+        Box::new( TriangleMesh { bvh: Bvh::new(faces), mesh, area_cdf } )
+    }
+
+    pub fn area(&self) -> f32 {
+        self.area_cdf.last().copied().unwrap_or(0.0)
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.bvh.len()
+    }
+}
+
+impl Hittable for TriangleMesh {
+
+    fn hit(&self, ray:&Ray, tmin:f32, tmax:f32) -> Option<HitRecord> {
+        self.bvh.hit(ray, tmin, tmax)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mesh.material.is_emissive()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.mesh.material)
+    }
+
+    fn sample(&self, origin:Vector3, u:Vector2) -> Option<ShapeSample> {
+        let area = self.area();
+        if area <= 0.0 {
+            return None;
+        }
+        // pick a face proportional to its area, then reuse the
+        // remainder of u.x to place the point within that face
+        let target = u.x * area;
+        let face = usize::min(self.area_cdf.partition_point(|&a| a <= target), self.area_cdf.len()-1);
+        let start = match face {
+            0 => 0.0,
+            _ => self.area_cdf[face-1],
+        };
+        let face_area = self.area_cdf[face] - start;
+        let ux = f32::clamp((target - start) / face_area, 0.0, 1.0);
+
+        let (p0, p1, p2) = self.mesh.face_positions(face);
+        let (b1, b2) = sample_triangle(Vector2::new(ux, u.y));
+        let point = barycentric(b1, b2, p0, p1, p2);
+        let normal = (p1-p0).cross(p2-p0).normalize();
+        let pdf = area_to_solid_angle(1.0 / area, origin, point, normal);
+        Some(ShapeSample { point, normal, pdf })
+    }
+
+    fn pdf(&self, origin:Vector3, hit:&HitRecord) -> f32 {
+        area_to_solid_angle(1.0 / self.area(), origin, hit.point, hit.normal)
+    }
+}
+
+const BVH_BINS: usize = 12;
+const BVH_MAX_LEAF_SIZE: usize = 4;
+const BVH_TRAVERSAL_COST: f32 = 1.0;
+// below this depth only median splits are made, which bounds
+// the tree depth and therefore the traversal stack size
+const BVH_MAX_SAH_DEPTH: usize = 64;
+const BVH_STACK_SIZE: usize = 128;
+
+enum BvhNode {
+    Leaf {
+        bbox: Aabb,
+        start: usize,
+        count: usize,
+    },
+    Interior {
+        bbox: Aabb,
+        // the left child always directly follows its parent
+        right: usize,
+        axis: usize,
+    },
+}
+
+impl BvhNode {
+    #[inline]
+    fn bbox(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bbox, .. } => bbox,
+            BvhNode::Interior { bbox, .. } => bbox,
+        }
+    }
+}
+
+struct BvhBuildItem {
+    bbox: Aabb,
+    centroid: Vector3,
+    index: usize,
+}
+
+/// Bounding volume hierarchy over a set of hittables, built top-down
+/// with a binned surface area heuristic and stored as a flat node array.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    objects: Vec<Box<dyn Hittable>>,
+    /// position of each object in the vector the BVH was built from
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+
+    pub fn new(objects:Vec<Box<dyn Hittable>>) -> Bvh {
+        let mut items: Vec<BvhBuildItem> = objects.iter().enumerate().map( |(index, object)| {
+            let bbox = object.bounding_box();
+            BvhBuildItem { bbox, centroid: bbox.centroid(), index }
+        }).collect();
+
+        let mut nodes = Vec::with_capacity(2 * items.len());
+        if !items.is_empty() {
+            Bvh::build_recursive(&mut nodes, &mut items, 0, 0);
+        }
+
+        // reorder objects so every leaf references a contiguous range
+        let mut slots: Vec<Option<Box<dyn Hittable>>> = objects.into_iter().map(Some).collect();
+        let objects = items.iter().map( |item| {
+            slots[item.index].take().unwrap()
+        }).collect();
+        let indices = items.iter().map(|item| item.index).collect();
+
+        Bvh { nodes, objects, indices }
+    }
+
+    /// gives back the hittables in the order they were given, eg. to rebuild after adding more
+    pub fn into_objects(self) -> Vec<Box<dyn Hittable>> {
+        let mut slots: Vec<Option<Box<dyn Hittable>>> = (0..self.objects.len()).map(|_| None).collect();
+        for (object, index) in self.objects.into_iter().zip(self.indices) {
+            slots[index] = Some(object);
+        }
+        slots.into_iter().map(Option::unwrap).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    fn build_recursive(nodes:&mut Vec<BvhNode>, items:&mut [BvhBuildItem], start:usize, depth:usize) -> usize {
+        let node_index = nodes.len();
+        let count = items.len();
+
+        let bbox = items.iter().fold(Aabb::empty(), |b, item| b.union(&item.bbox));
+        let centroid_bounds = items.iter().fold(Aabb::empty(), |b, item| b.grow(item.centroid));
+
+        let leaf = BvhNode::Leaf { bbox, start, count };
+        if count == 1 {
+            nodes.push(leaf);
+            return node_index;
+        }
+
+        let split = match depth < BVH_MAX_SAH_DEPTH {
+            true => Bvh::find_split(items, &bbox, &centroid_bounds),
+            false => None,
+        };
+        let (axis, mid) = match split {
+            Some(split) => split,
+            None if count <= BVH_MAX_LEAF_SIZE => {
+                nodes.push(leaf);
+                return node_index;
+            }
+            None => {
+                // SAH found nothing worthwhile but too many objects for a leaf,
+                // fall back to a median split on the widest centroid axis
+                let axis = centroid_bounds.largest_axis();
+                items.sort_unstable_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+                (axis, count / 2)
+            }
+        };
+
+        // placeholder until the right child index is known
+        nodes.push(BvhNode::Interior { bbox, right: 0, axis });
+
+        let (left_items, right_items) = items.split_at_mut(mid);
+        Bvh::build_recursive(nodes, left_items, start, depth+1);
+        let right_index = Bvh::build_recursive(nodes, right_items, start + mid, depth+1);
+        nodes[node_index] = BvhNode::Interior { bbox, right: right_index, axis };
+
+        node_index
+    }
+
+    /// binned SAH, returns the split axis and the number of items
+    /// partitioned to the left, or None if a leaf is cheaper
+    fn find_split(items:&mut [BvhBuildItem], bbox:&Aabb, centroid_bounds:&Aabb) -> Option<(usize, usize)> {
+        let count = items.len();
+        let extent = centroid_bounds.extent();
+        let bin_of = |centroid:f32, axis:usize| -> usize {
+            let rel = (centroid - centroid_bounds.min[axis]) / extent[axis];
+            usize::min((rel * BVH_BINS as f32) as usize, BVH_BINS-1)
+        };
+
+        let mut best: Option<(usize, usize, f32)> = None;
+
+        for axis in 0..3 {
+            if extent[axis] <= 0.0 {
+                continue;
+            }
+            let mut bins = [(Aabb::empty(), 0usize); BVH_BINS];
+            for item in items.iter() {
+                let bin = &mut bins[bin_of(item.centroid[axis], axis)];
+                bin.0 = bin.0.union(&item.bbox);
+                bin.1 += 1;
+            }
+
+            // sweep from the right to accumulate the area of every suffix
+            let mut right_area = [0.0; BVH_BINS];
+            let mut right_bbox = Aabb::empty();
+            for i in (1..BVH_BINS).rev() {
+                right_bbox = right_bbox.union(&bins[i].0);
+                right_area[i] = right_bbox.surface_area();
+            }
+
+            let mut left_bbox = Aabb::empty();
+            let mut left_count = 0;
+            for i in 0..BVH_BINS-1 {
+                left_bbox = left_bbox.union(&bins[i].0);
+                left_count += bins[i].1;
+                let right_count = count - left_count;
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+                let cost = left_count as f32 * left_bbox.surface_area()
+                    + right_count as f32 * right_area[i+1];
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, i, cost));
+                }
+            }
+        }
+
+        let (axis, split_bin, cost) = best?;
+
+        let area = bbox.surface_area();
+        let leaf_cost = count as f32;
+        let split_cost = match area > 0.0 {
+            true => BVH_TRAVERSAL_COST + cost / area,
+            false => f32::INFINITY,
+        };
+        if count <= BVH_MAX_LEAF_SIZE && leaf_cost <= split_cost {
+            return None;
+        }
+
+        // partition in place around the chosen bin boundary
+        let mut mid = 0;
+        for i in 0..count {
+            if bin_of(items[i].centroid[axis], axis) <= split_bin {
+                items.swap(i, mid);
+                mid += 1;
+            }
+        }
+        Some((axis, mid))
+    }
+}
+
+impl Bvh {
+    /// closest hit along with the position of the hit object
+    /// in the vector the BVH was built from
+    pub fn hit_object(&self, ray:&Ray, tmin:f32, tmax:f32) -> Option<(usize, HitRecord)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let origin = ray.origin();
+        let dir = ray.direction();
+        let inv_dir = Vector3::new(1.0/dir.x, 1.0/dir.y, 1.0/dir.z);
+
+        let mut result:Option<(usize, HitRecord)> = None;
+        let mut closest = tmax;
+
+        let mut stack = [0usize; BVH_STACK_SIZE];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node_index = stack[stack_size];
+            let node = &self.nodes[node_index];
+
+            if !node.bbox().hit(origin, inv_dir, tmin, closest) {
+                continue;
+            }
+            match node {
+                BvhNode::Leaf { start, count, .. } => {
+                    for i in *start..*start+*count {
+                        if let Some(hit_result) = self.objects[i].hit(ray, tmin, closest) {
+                            closest = hit_result.t;
+                            result = Some((self.indices[i], hit_result));
+                        }
+                    }
+                }
+                BvhNode::Interior { right, axis, .. } => {
+                    // visit the near child first so the far one is more likely culled
+                    let left = node_index + 1;
+                    let (near, far) = match dir[*axis] < 0.0 {
+                        true => (*right, left),
+                        false => (left, *right),
+                    };
+                    stack[stack_size] = far;
+                    stack[stack_size+1] = near;
+                    stack_size += 2;
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray:&Ray, tmin:f32, tmax:f32) -> Option<HitRecord> {
+        self.hit_object(ray, tmin, tmax).map(|(_, hit)| hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(root) => *root.bbox(),
+            None => Aabb::empty(),
+        }
+    }
+}
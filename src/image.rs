@@ -1,6 +1,7 @@
 
 use std::ops::Mul;
 use std::fs::File;
+use std::io::Read;
 use std::io::Write;
 use std::ops::Add;
 use std::sync::{Mutex};
@@ -19,6 +20,102 @@ pub struct Coloru8 {
     pub blue:u8,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+    /// scene linear OpenEXR
+    Exr(ExrPixelType),
+    /// scene linear Radiance rgbe
+    Hdr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl ImageFormat {
+    pub fn from_extension(ext:&str) -> Option<ImageFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "bmp" => Some(ImageFormat::Bmp),
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr(ExrPixelType::Half)),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path:&str) -> Option<ImageFormat> {
+        let ext = std::path::Path::new(path).extension()?.to_str()?;
+        ImageFormat::from_extension(ext)
+    }
+
+    /// formats written from the linear radiance rather than the 8 bit pixels
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, ImageFormat::Exr(_) | ImageFormat::Hdr)
+    }
+}
+
+/// Running mean and variance of a pixel's sample luminance, kept with
+/// Welford's update so long runs don't lose precision
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampleStatistics {
+    pub count: f32,
+    pub mean: f32,
+    /// sum of squared differences from the mean
+    pub m2: f32,
+}
+
+impl SampleStatistics {
+    pub fn push(&mut self, value:f32) {
+        self.count += 1.0;
+        let delta = value - self.mean;
+        self.mean += delta / self.count;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// combines the statistics of two disjoint sets of samples
+    pub fn merge(&self, other:&SampleStatistics) -> SampleStatistics {
+        let count = self.count + other.count;
+        if count == 0.0 {
+            return SampleStatistics::default();
+        }
+        let delta = other.mean - self.mean;
+        SampleStatistics {
+            count,
+            mean: self.mean + delta * other.count / count,
+            m2: self.m2 + other.m2 + delta * delta * self.count * other.count / count,
+        }
+    }
+
+    /// unbiased variance of the samples, zero until there are two
+    pub fn variance(&self) -> f32 {
+        match self.count > 1.0 {
+            true => self.m2 / (self.count - 1.0),
+            false => 0.0,
+        }
+    }
+
+    /// variance of the mean, shrinks as samples are added
+    pub fn variance_of_mean(&self) -> f32 {
+        match self.count > 1.0 {
+            true => self.variance() / self.count,
+            false => 0.0,
+        }
+    }
+}
+
+/// Extra channels written next to the rgba channels of an OpenEXR image,
+/// named `name.channel` and taken in order from the averaged radiance of `image`
+pub struct ExrLayer<'a> {
+    pub name: &'a str,
+    pub channels: &'a [&'a str],
+    pub pixel_type: ExrPixelType,
+    pub image: &'a Image,
+}
+
 #[derive(Clone, Copy)]
 pub struct Region {
     pub x:usize,
@@ -30,6 +127,10 @@ pub struct Region {
 pub struct Image {
     region: Region,
     bytes: Mutex<Vec<u8>>,
+    /// linear radiance summed over samples in rgb, total sample weight in alpha
+    radiance: Mutex<Vec<[f32; 4]>>,
+    /// luminance statistics of the samples in `radiance`
+    statistics: Mutex<Vec<SampleStatistics>>,
 }
 
 pub struct RegionIter {
@@ -150,6 +251,15 @@ impl Region {
         chunks
     }
 
+    /// grown by `margin` pixels on every side, without going outside of `bounds`
+    pub fn expand(&self, margin:usize, bounds:Region) -> Region {
+        let x = usize::max(self.x.saturating_sub(margin), bounds.x);
+        let y = usize::max(self.y.saturating_sub(margin), bounds.y);
+        let right = usize::min(self.x + self.width + margin, bounds.x + bounds.width);
+        let top = usize::min(self.y + self.height + margin, bounds.y + bounds.height);
+        Region { x, y, width: right.saturating_sub(x), height: top.saturating_sub(y) }
+    }
+
 }
 
 impl Image {
@@ -162,7 +272,9 @@ impl Image {
     
     pub fn new_with_region(region:Region) -> Image {
         let bytes = Mutex::new(vec![0; 3 * region.width * region.height]);
-        Image { region, bytes }
+        let radiance = Mutex::new(vec![[0.0; 4]; region.width * region.height]);
+        let statistics = Mutex::new(vec![SampleStatistics::default(); region.width * region.height]);
+        Image { region, bytes, radiance, statistics }
     }
 
     #[inline]
@@ -175,6 +287,11 @@ impl Image {
         self.region.height
     }
 
+    #[inline]
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
     #[inline]
     pub fn y_range_iter(&self) -> std::ops::Range<usize> {
         self.region.y..self.region.y+self.region.height
@@ -210,8 +327,161 @@ impl Image {
             src_offset += src_stride;
             dst_offset += dst_stride;
         }
+        drop(dst_bytes);
+
+        let mut dst_radiance = self.radiance.lock().unwrap();
+        let src_radiance = src.radiance.lock().unwrap();
+        for j in 0..usize::min(src.height(), self.height().saturating_sub(y)) {
+            let n = usize::min(src.width(), self.width().saturating_sub(x));
+            let dst_offset = x + (y+j)*self.width();
+            let src_offset = j*src.width();
+            dst_radiance[dst_offset..dst_offset+n].copy_from_slice(&src_radiance[src_offset..src_offset+n]);
+        }
+        drop(dst_radiance);
+
+        let mut dst_statistics = self.statistics.lock().unwrap();
+        let src_statistics = src.statistics.lock().unwrap();
+        for j in 0..usize::min(src.height(), self.height().saturating_sub(y)) {
+            let n = usize::min(src.width(), self.width().saturating_sub(x));
+            let dst_offset = x + (y+j)*self.width();
+            let src_offset = j*src.width();
+            dst_statistics[dst_offset..dst_offset+n].copy_from_slice(&src_statistics[src_offset..src_offset+n]);
+        }
+    }
+
+    /// adds the radiance and sample statistics of `src` to this image where
+    /// they overlap, the 8 bit pixels are left as they are
+    pub fn accumulate(&self, src:&Image) {
+        let mut dst_radiance = self.radiance.lock().unwrap();
+        let mut dst_statistics = self.statistics.lock().unwrap();
+        let src_radiance = src.radiance.lock().unwrap();
+        let src_statistics = src.statistics.lock().unwrap();
+        for y in src.y_range_iter() {
+            for x in src.x_range_iter() {
+                if let (Some(dst), Some(i)) = (self.radiance_index(x, y), src.radiance_index(x, y)) {
+                    for c in 0..4 {
+                        dst_radiance[dst][c] += src_radiance[i][c];
+                    }
+                    dst_statistics[dst] = dst_statistics[dst].merge(&src_statistics[i]);
+                }
+            }
+        }
+    }
+
+    /// copy of the part of this image inside `region`, the reverse of `blit`.
+    /// Pixels of `region` outside of this image are left empty.
+    pub fn crop(&self, region:Region) -> Image {
+        let cropped = Image::new_with_region(region);
+        let src_bytes = self.bytes.lock().unwrap();
+        let src_radiance = self.radiance.lock().unwrap();
+        let src_statistics = self.statistics.lock().unwrap();
+        let mut dst_bytes = cropped.bytes.lock().unwrap();
+        let mut dst_radiance = cropped.radiance.lock().unwrap();
+        let mut dst_statistics = cropped.statistics.lock().unwrap();
+        for y in cropped.y_range_iter() {
+            for x in cropped.x_range_iter() {
+                if let (Some(src), Some(dst)) = (self.radiance_index(x, y), cropped.radiance_index(x, y)) {
+                    dst_bytes[3*dst..3*dst+3].copy_from_slice(&src_bytes[3*src..3*src+3]);
+                    dst_radiance[dst] = src_radiance[src];
+                    dst_statistics[dst] = src_statistics[src];
+                }
+            }
+        }
+        drop((dst_bytes, dst_radiance, dst_statistics));
+        cropped
+    }
+
+    /// index into the radiance buffer, None outside of the region
+    #[inline]
+    fn radiance_index(&self, x:usize, y:usize) -> Option<usize> {
+        let x = x.checked_sub(self.region.x)?;
+        let y = y.checked_sub(self.region.y)?;
+        match x < self.region.width && y < self.region.height {
+            true => Some(x + y*self.region.width),
+            false => None,
+        }
+    }
+
+    /// accumulates `color`, the sum of `weight` samples, into the radiance buffer
+    pub fn add_radiance(&self, x:usize, y:usize, color:Color, weight:f32) {
+        if let Some(i) = self.radiance_index(x, y) {
+            let mut radiance = self.radiance.lock().unwrap();
+            let pixel = &mut radiance[i];
+            pixel[0] += color.red;
+            pixel[1] += color.green;
+            pixel[2] += color.blue;
+            pixel[3] += weight;
+        }
+    }
+
+    /// accumulates `color` into each of the pixels in `taps`, weighted by its weight
+    pub fn splat(&self, color:Color, taps:&[(usize, usize, f32)]) {
+        let mut radiance = self.radiance.lock().unwrap();
+        for &(x, y, weight) in taps {
+            if let Some(i) = self.radiance_index(x, y) {
+                let pixel = &mut radiance[i];
+                pixel[0] += weight * color.red;
+                pixel[1] += weight * color.green;
+                pixel[2] += weight * color.blue;
+                pixel[3] += weight;
+            }
+        }
+    }
+
+    /// replaces whatever was accumulated at the pixel with a single sample
+    pub fn set_radiance(&self, x:usize, y:usize, color:Color) {
+        if let Some(i) = self.radiance_index(x, y) {
+            self.radiance.lock().unwrap()[i] = [color.red, color.green, color.blue, 1.0];
+        }
+    }
+
+    /// average radiance of the samples accumulated at the pixel
+    pub fn get_radiance(&self, x:usize, y:usize) -> Color {
+        let i = self.radiance_index(x, y).expect("pixel outside of the image");
+        let [r, g, b, weight] = self.radiance.lock().unwrap()[i];
+        match weight > 0.0 {
+            true => Color::new(r / weight, g / weight, b / weight),
+            false => Color::black(),
+        }
+    }
+
+    /// total weight of the samples accumulated at the pixel
+    pub fn get_weight(&self, x:usize, y:usize) -> f32 {
+        let i = self.radiance_index(x, y).expect("pixel outside of the image");
+        self.radiance.lock().unwrap()[i][3]
+    }
+
+    /// folds the luminance statistics of newly added samples into the pixel's
+    pub fn add_statistics(&self, x:usize, y:usize, samples:&SampleStatistics) {
+        if let Some(i) = self.radiance_index(x, y) {
+            let mut statistics = self.statistics.lock().unwrap();
+            statistics[i] = statistics[i].merge(samples);
+        }
+    }
+
+    pub fn get_statistics(&self, x:usize, y:usize) -> SampleStatistics {
+        let i = self.radiance_index(x, y).expect("pixel outside of the image");
+        self.statistics.lock().unwrap()[i]
     }
 
+    /// averaged radiance as top down rgba rows, alpha is 1 where samples landed
+    fn radiance_rows(&self) -> Vec<[f32; 4]> {
+        let w = self.region.width;
+        let h = self.region.height;
+        let radiance = self.radiance.lock().unwrap();
+        let mut rows = Vec::with_capacity(w*h);
+        for y in (0..h).rev() {
+            for &[r, g, b, weight] in &radiance[y*w..(y+1)*w] {
+                rows.push(match weight > 0.0 {
+                    true => [r / weight, g / weight, b / weight, 1.0],
+                    false => [0.0; 4],
+                });
+            }
+        }
+        rows
+    }
+
+    #[allow(clippy::identity_op)]
     pub fn set_pixel_color_u8(&self, x:usize, y:usize, color:Coloru8) {
         let width = self.region.width;
         let height = self.region.height;
@@ -241,6 +511,20 @@ impl Image {
         bytes[i+2] = color.red;
     }
 
+    pub fn get_pixel_color_u8(&self, x:usize, y:usize) -> Coloru8 {
+        let x = x - self.region.x;
+        let y = y - self.region.y;
+        let i = 3*x + y*3*self.region.width;
+        let bytes = self.bytes.lock().unwrap();
+
+        // (B,G,R)
+        Coloru8 {
+            red: bytes[i+2],
+            green: bytes[i+1],
+            blue: bytes[i],
+        }
+    }
+
     pub fn set_pixel_color(&self, x:usize, y:usize, color:Color) {
         let normalize = |f:f32| -> u8 {
             let n = (255.0 * f) as u8;
@@ -253,11 +537,60 @@ impl Image {
         });
     }
 
-    pub fn write_bmp(&self, path: &str) {
-        let mut file = match File::create(&path) {
-            Err(why) => panic!("couldn't create {}: {}", path, why),
-            Ok(file) => file,
-        };
+    pub fn write(&self, path:&str, format:ImageFormat) -> std::io::Result<()> {
+        match format {
+            ImageFormat::Bmp => self.write_bmp(path),
+            ImageFormat::Png => self.write_png(path),
+            ImageFormat::Exr(pixel_type) => self.write_exr(path, pixel_type),
+            ImageFormat::Hdr => self.write_hdr(path),
+        }
+    }
+
+    /// writes the linear radiance as a zip compressed scanline OpenEXR with rgba channels
+    pub fn write_exr(&self, path: &str, pixel_type:ExrPixelType) -> std::io::Result<()> {
+        self.write_exr_with_layers(path, pixel_type, &[])
+    }
+
+    /// writes the linear radiance like `write_exr` plus the channels of every layer
+    pub fn write_exr_with_layers(&self, path: &str, pixel_type:ExrPixelType, layers:&[ExrLayer]) -> std::io::Result<()> {
+        let w = self.region.width;
+        let h = self.region.height;
+        if let Some(layer) = layers.iter().find(|layer| layer.image.width() != w || layer.image.height() != h) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("layer {} is {}x{} but the image is {}x{}", layer.name, layer.image.width(), layer.image.height(), w, h)));
+        }
+
+        let rows = self.radiance_rows();
+        let mut channels: Vec<crate::exr::Channel> = ["R", "G", "B", "A"].iter().enumerate().map( |(c, name)| {
+            crate::exr::Channel {
+                name: name.to_string(),
+                pixel_type,
+                values: rows.iter().map(|pixel| pixel[c]).collect(),
+            }
+        }).collect();
+        for layer in layers {
+            let rows = layer.image.radiance_rows();
+            for (c, name) in layer.channels.iter().enumerate().take(3) {
+                channels.push(crate::exr::Channel {
+                    name: format!("{}.{}", layer.name, name),
+                    pixel_type: layer.pixel_type,
+                    values: rows.iter().map(|pixel| pixel[c]).collect(),
+                });
+            }
+        }
+        let encoded = crate::exr::encode(w, h, channels);
+        File::create(path)?.write_all(&encoded)
+    }
+
+    /// writes the linear radiance as a run length encoded Radiance rgbe image
+    pub fn write_hdr(&self, path: &str) -> std::io::Result<()> {
+        let encoded = crate::hdr::encode(self.region.width, self.region.height, &self.radiance_rows());
+        File::create(path)?.write_all(&encoded)
+    }
+
+    #[allow(clippy::identity_op)]
+    pub fn write_bmp(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
         let w = self.region.width;
         let h = self.region.height;
         let filesize:u32 = 52 + (3*w*h) as u32;
@@ -280,8 +613,8 @@ impl Image {
         info_header[10] = (0xFFFF & h >>16) as u8;
         info_header[11] = (0xFFFF & h >>24) as u8;
 
-        file.write_all(&file_header).unwrap();
-        file.write_all(&info_header).unwrap();
+        file.write_all(&file_header)?;
+        file.write_all(&info_header)?;
 
         let stride = 3*w;
         let padding = (4 - (stride) % 4) % 4;
@@ -290,8 +623,117 @@ impl Image {
 
         for y in 0..h {
             let offset = y*stride;
-            file.write_all(&bytes[offset..offset+stride]).unwrap();
-            file.write_all(&stride_pad[0..padding]).unwrap();
+            file.write_all(&bytes[offset..offset+stride])?;
+            file.write_all(&stride_pad[0..padding])?;
+        }
+        Ok(())
+    }
+
+    pub fn write_png(&self, path: &str) -> std::io::Result<()> {
+        self.write_png_with_alpha(path, None)
+    }
+
+    /// `alpha` holds one coverage byte per pixel, laid out like the
+    /// pixels with the bottom row first
+    pub fn write_png_with_alpha(&self, path: &str, alpha:Option<&[u8]>) -> std::io::Result<()> {
+        let w = self.region.width;
+        let h = self.region.height;
+        if let Some(alpha) = alpha {
+            if alpha.len() != w*h {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                    format!("alpha has {} values for a {}x{} image", alpha.len(), w, h)));
+            }
+        }
+
+        let bpp = match alpha { Some(_) => 4, None => 3 };
+        let mut pixels = Vec::with_capacity(bpp*w*h);
+        {
+            let bytes = self.bytes.lock().unwrap();
+            // png rows go top down
+            for y in (0..h).rev() {
+                for x in 0..w {
+                    let i = 3*(x + y*w);
+                    pixels.extend_from_slice(&[bytes[i+2], bytes[i+1], bytes[i]]);
+                    if let Some(alpha) = alpha {
+                        pixels.push(alpha[x + y*w]);
+                    }
+                }
+            }
+        }
+        let encoded = crate::png::encode(w, h, alpha.is_some(), &pixels);
+        File::create(path)?.write_all(&encoded)
+    }
+
+    /// reads a Radiance rgbe image into the radiance buffer, the 8 bit pixels stay black
+    pub fn read_hdr(path: &str) -> std::io::Result<Image> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        let (w, h, pixels) = crate::hdr::decode(&data)
+            .map_err(|msg| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, msg)))?;
+
+        let image = Image::new(w, h);
+        {
+            let mut radiance = image.radiance.lock().unwrap();
+            for (i, [r, g, b]) in pixels.into_iter().enumerate() {
+                // file rows go top down
+                let (x, y) = (i % w, h - 1 - i / w);
+                radiance[x + y*w] = [r, g, b, 1.0];
+            }
+        }
+        Ok(image)
+    }
+
+    /// reads uncompressed 24 or 32 bit bitmaps, like the ones written by `write_bmp`
+    pub fn read_bmp(path: &str) -> std::io::Result<Image> {
+        let invalid = |msg:&str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, msg));
+
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        if data.len() < 54 || &data[0..2] != b"BM" {
+            return Err(invalid("not a bitmap"));
+        }
+        let u16_at = |i:usize| u16::from_le_bytes([data[i], data[i+1]]);
+        let u32_at = |i:usize| u32::from_le_bytes([data[i], data[i+1], data[i+2], data[i+3]]);
+
+        let offset = u32_at(10) as usize;
+        let width = u32_at(18) as i32;
+        let height = u32_at(22) as i32;
+        let bpp = u16_at(28) as usize;
+        let compression = u32_at(30);
+
+        // 32 bit images are allowed to use bitfields, assumed to be BGRA
+        if (bpp != 24 && bpp != 32) || !(compression == 0 || (bpp == 32 && compression == 3)) {
+            return Err(invalid("only uncompressed 24 and 32 bit bitmaps are supported"));
+        }
+        if width <= 0 || height == 0 {
+            return Err(invalid("bad dimensions"));
+        }
+        // a negative height means the rows are stored top down
+        let top_down = height < 0;
+        let w = width as usize;
+        let h = height.unsigned_abs() as usize;
+
+        let pixel_size = bpp / 8;
+        let stride = (pixel_size*w).div_ceil(4) * 4;
+        if data.len() < offset + stride*h {
+            return Err(invalid("truncated pixel data"));
+        }
+
+        let image = Image::new(w, h);
+        {
+            let mut bytes = image.bytes.lock().unwrap();
+            for y in 0..h {
+                let row = match top_down {
+                    true => h - 1 - y,
+                    false => y,
+                };
+                let src = &data[offset + row*stride..];
+                for x in 0..w {
+                    let i = 3*(x + y*w);
+                    bytes[i..i+3].copy_from_slice(&src[x*pixel_size..x*pixel_size+3]);
+                }
+            }
         }
+        Ok(image)
     }
 }
@@ -0,0 +1,299 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ray::Ray;
+use crate::image::Color;
+use crate::scene::Scene;
+use crate::scene::LightId;
+use crate::hittables::HitRecord;
+use crate::sampler::Sampler;
+use crate::sampler::sample_unit_sphere;
+
+use cgmath::InnerSpace;
+
+type Vector3 = cgmath::Vector3<f32>;
+
+/// closest and farthest hits a ray may report
+pub const TMIN: f32 = 0.001;
+pub const TMAX: f32 = 1000.0;
+
+/// relative distance a shadow ray may end from the sampled light point
+const SHADOW_EPSILON: f32 = 1e-3;
+
+/// Estimates the radiance arriving at the camera along a ray, the
+/// renderer averages the estimates of all samples in a pixel. Random
+/// decisions draw from `sampler`, which the renderer starts per sample.
+pub trait Integrator: Sync+Send {
+    fn radiance(&self, scene:&Scene, ray:&Ray, sampler:&mut dyn Sampler) -> Color;
+}
+
+/// Unidirectional path tracer with next event estimation, emission found
+/// by BSDF sampling is weighted against light sampling with MIS
+pub struct PathTracer {
+    pub max_depth: usize,
+}
+
+impl PathTracer {
+    pub fn new(max_depth:usize) -> PathTracer {
+        PathTracer { max_depth }
+    }
+
+    fn trace(&self, scene:&Scene, ray:&Ray, depth:usize, bounce:Option<Bounce>, sampler:&mut dyn Sampler) -> Color {
+        if depth == 0 {
+            return environment(scene, ray, None);
+        }
+        match scene.hit(ray, TMIN, TMAX) {
+            None => environment(scene, ray, bounce),
+            Some(hit) => self.on_hit(scene, ray, depth, hit, bounce, sampler),
+        }
+    }
+
+    fn on_hit(&self, scene:&Scene, ray:&Ray, depth:usize, hit:HitRecord, bounce:Option<Bounce>, sampler:&mut dyn Sampler) -> Color {
+        let emitted = emitted(scene, &hit, bounce);
+        let scatter = match hit.material.scatter(ray, &hit, sampler) {
+            None => return emitted,
+            Some(scatter) => scatter,
+        };
+        match scatter.pdf {
+            // specular bounces can only find lights by hitting them
+            None => {
+                emitted + scatter.attenuation * self.trace(scene, &scatter.ray, depth-1, None, sampler)
+            }
+            Some(pdf) => {
+                let direct = direct_lighting(scene, ray, &hit, sampler);
+                let bounce = Bounce { origin: hit.point, pdf };
+                emitted + direct + scatter.attenuation * self.trace(scene, &scatter.ray, depth-1, Some(bounce), sampler)
+            }
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, scene:&Scene, ray:&Ray, sampler:&mut dyn Sampler) -> Color {
+        self.trace(scene, ray, self.max_depth, None, sampler)
+    }
+}
+
+/// Light arriving straight from emitters and the environment, without
+/// any diffuse interreflection. Specular surfaces are still followed up
+/// to `max_depth` bounces so glass and mirrors show what they reflect.
+pub struct DirectLighting {
+    pub max_depth: usize,
+}
+
+impl DirectLighting {
+    pub fn new(max_depth:usize) -> DirectLighting {
+        DirectLighting { max_depth }
+    }
+
+    fn trace(&self, scene:&Scene, ray:&Ray, depth:usize, sampler:&mut dyn Sampler) -> Color {
+        let hit = match scene.hit(ray, TMIN, TMAX) {
+            None => return environment(scene, ray, None),
+            Some(hit) => hit,
+        };
+        let emitted = hit.material.emitted(&hit);
+        let scatter = match hit.material.scatter(ray, &hit, sampler) {
+            None => return emitted,
+            Some(scatter) => scatter,
+        };
+        match scatter.pdf {
+            None if depth > 1 => {
+                emitted + scatter.attenuation * self.trace(scene, &scatter.ray, depth-1, sampler)
+            }
+            None => emitted,
+            Some(pdf) => {
+                // one light sample and one BSDF sample, each weighted against the other
+                let direct = direct_lighting(scene, ray, &hit, sampler);
+                let bounce = Some(Bounce { origin: hit.point, pdf });
+                let found = match scene.hit(&scatter.ray, TMIN, TMAX) {
+                    None => environment(scene, &scatter.ray, bounce),
+                    Some(light_hit) => self::emitted(scene, &light_hit, bounce),
+                };
+                emitted + direct + scatter.attenuation * found
+            }
+        }
+    }
+}
+
+impl Integrator for DirectLighting {
+    fn radiance(&self, scene:&Scene, ray:&Ray, sampler:&mut dyn Sampler) -> Color {
+        self.trace(scene, ray, self.max_depth, sampler)
+    }
+}
+
+/// White where the hemisphere above the first hit is open within `distance`,
+/// darker in creases and contact shadows. Misses are white.
+pub struct AmbientOcclusion {
+    pub distance: f32,
+}
+
+impl AmbientOcclusion {
+    pub fn new(distance:f32) -> AmbientOcclusion {
+        AmbientOcclusion { distance }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, scene:&Scene, ray:&Ray, sampler:&mut dyn Sampler) -> Color {
+        let hit = match scene.hit(ray, TMIN, TMAX) {
+            None => return Color::new(1.0, 1.0, 1.0),
+            Some(hit) => hit,
+        };
+        // cosine weighted about the shading normal, like a lambertian bounce
+        let direction = hit.shading_normal + sample_unit_sphere(sampler.next_2d());
+        if direction.magnitude2() < 1e-8 || cgmath::dot(direction, hit.normal) <= 0.0 {
+            return Color::black();
+        }
+        let occluder = scene.hit(&Ray::new(hit.point, direction.normalize()), TMIN, self.distance);
+        match occluder {
+            None => Color::new(1.0, 1.0, 1.0),
+            Some(_) => Color::black(),
+        }
+    }
+}
+
+/// What `DebugIntegrator` shows at the first hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugView {
+    /// shading normal remapped from [-1, 1] to [0, 1]
+    ShadingNormal,
+    /// geometric normal facing the ray, remapped to [0, 1]
+    GeometricNormal,
+    /// texture coordinates in red and green
+    Uv,
+    /// distance along the ray in scene units
+    Depth,
+    /// green where the front of a surface was hit, red for the back
+    FrontFace,
+}
+
+impl DebugView {
+    pub fn from_name(name:&str) -> Option<DebugView> {
+        match name {
+            "shading_normal" => Some(DebugView::ShadingNormal),
+            "geometric_normal" => Some(DebugView::GeometricNormal),
+            "uv" => Some(DebugView::Uv),
+            "depth" => Some(DebugView::Depth),
+            "front_face" => Some(DebugView::FrontFace),
+            _ => None,
+        }
+    }
+}
+
+/// Shows a property of the first hit instead of lighting, misses are black
+pub struct DebugIntegrator {
+    pub view: DebugView,
+}
+
+impl DebugIntegrator {
+    pub fn new(view:DebugView) -> DebugIntegrator {
+        DebugIntegrator { view }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn radiance(&self, scene:&Scene, ray:&Ray, _sampler:&mut dyn Sampler) -> Color {
+        let hit = match scene.hit(ray, TMIN, TMAX) {
+            None => return Color::black(),
+            Some(hit) => hit,
+        };
+        let remap = |n:Vector3| Color::new(0.5 * (n.x + 1.0), 0.5 * (n.y + 1.0), 0.5 * (n.z + 1.0));
+        match self.view {
+            DebugView::ShadingNormal => remap(hit.shading_normal),
+            DebugView::GeometricNormal => remap(hit.normal),
+            DebugView::Uv => Color::new(hit.uv.x - hit.uv.x.floor(), hit.uv.y - hit.uv.y.floor(), 0.0),
+            DebugView::Depth => {
+                let t = hit.t * ray.direction().magnitude();
+                Color::new(t, t, t)
+            }
+            DebugView::FrontFace => match hit.front_face {
+                true => Color::new(0.0, 1.0, 0.0),
+                false => Color::new(1.0, 0.0, 0.0),
+            },
+        }
+    }
+}
+
+/// where a ray was scattered from and the pdf of its direction, needed to
+/// weight emission found by BSDF sampling against light sampling
+#[derive(Clone, Copy)]
+struct Bounce {
+    origin: Vector3,
+    pdf: f32,
+}
+
+/// environment radiance, weighted like emission when the
+/// environment is also sampled as a light
+fn environment(scene:&Scene, ray:&Ray, bounce:Option<Bounce>) -> Color {
+    let dir = ray.direction().normalize();
+    let radiance = scene.environment().radiance(dir);
+    match bounce {
+        Some(bounce) => power_heuristic(bounce.pdf, scene.environment_pdf(dir)) * radiance,
+        None => radiance,
+    }
+}
+
+/// emission at the hit, weighted against the chance that
+/// light sampling at the previous bounce found the same point
+fn emitted(scene:&Scene, hit:&HitRecord, bounce:Option<Bounce>) -> Color {
+    let emitted = hit.material.emitted(hit);
+    match (bounce, hit.light) {
+        (Some(bounce), Some(light)) => {
+            let light_pdf = scene.light_pdf(light, bounce.origin, hit);
+            power_heuristic(bounce.pdf, light_pdf) * emitted
+        }
+        _ => emitted,
+    }
+}
+
+/// next event estimation, samples a point on one light and
+/// weights it against the chance of the BSDF choosing it
+fn direct_lighting(scene:&Scene, ray:&Ray, hit:&HitRecord, sampler:&mut dyn Sampler) -> Color {
+    let pick = sampler.next_1d();
+    let sample = match scene.sample_light(hit.point, pick, sampler.next_2d()) {
+        None => return Color::black(),
+        Some(sample) => sample,
+    };
+    if !(sample.pdf > 0.0 && sample.pdf.is_finite()) {
+        return Color::black();
+    }
+    let direction = sample.direction;
+
+    let f = hit.material.eval(ray, hit, direction);
+    if f.red <= 0.0 && f.green <= 0.0 && f.blue <= 0.0 {
+        return Color::black();
+    }
+
+    // the shadow ray tests visibility and finds the radiance arriving from the light
+    let shadow = Ray::new(hit.point, direction);
+    let radiance = match sample.light {
+        LightId::Shape(light) => {
+            let dist = sample.distance;
+            match scene.hit(&shadow, TMIN, dist * (1.0 + SHADOW_EPSILON)) {
+                Some(light_hit) if light_hit.light == Some(light) && light_hit.t >= dist * (1.0 - SHADOW_EPSILON) => {
+                    light_hit.material.emitted(&light_hit)
+                }
+                _ => return Color::black(),
+            }
+        }
+        LightId::Environment => match scene.hit(&shadow, TMIN, TMAX) {
+            None => scene.environment().radiance(direction),
+            Some(_) => return Color::black(),
+        },
+    };
+    let bsdf_pdf = hit.material.pdf(ray, hit, direction);
+    let weight = power_heuristic(sample.pdf, bsdf_pdf);
+    (weight / sample.pdf) * (f * radiance)
+}
+
+/// multiple importance sampling weight of strategy `a` against `b`
+#[inline]
+fn power_heuristic(a:f32, b:f32) -> f32 {
+    let a2 = a * a;
+    let b2 = b * b;
+    match a2 + b2 > 0.0 {
+        true => a2 / (a2 + b2),
+        false => 0.0,
+    }
+}
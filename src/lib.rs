@@ -3,7 +3,23 @@ pub mod image;
 pub mod camera;
 pub mod scene;
 pub mod renderer;
+pub mod session;
+pub mod driver;
+pub mod sampler;
+pub mod filter;
+pub mod integrators;
+pub mod aov;
+pub mod denoise;
 pub mod hittables;
 pub mod materials;
+pub mod textures;
+pub mod tonemap;
+pub mod environment;
+pub mod sky;
 pub mod utils;
-mod randlut;
\ No newline at end of file
+pub mod obj;
+pub mod description;
+mod deflate;
+mod png;
+mod exr;
+mod hdr;
\ No newline at end of file
@@ -1,98 +1,243 @@
+mod cli;
 
-use std::sync::Arc;
-use std::sync::RwLock;
 use std::time::Instant;
-use threadpool::ThreadPool;
+use std::mem::discriminant;
 
 use rustytracer::utils;
-use rustytracer::camera::Camera;
 use rustytracer::scene::Scene;
-use rustytracer::image::Image;
 use rustytracer::image::Region;
-use rustytracer::renderer::Renderer;
 use rustytracer::renderer::RenderTarget;
+use rustytracer::session::RenderSession;
+use rustytracer::session::StopReason;
+use rustytracer::driver::RenderDriver;
+use rustytracer::denoise::AtrousFilter;
+use rustytracer::description::SceneDescription;
+use rustytracer::description::ImageDescription;
+use rustytracer::description::CameraDescription;
+use rustytracer::description::ProjectionDescription;
+use rustytracer::description::RendererDescription;
+use rustytracer::description::FilterDescription;
+
+use cli::Command;
+use cli::Options;
 
 type Vector3 = cgmath::Vector3<f32>;
 use cgmath::InnerSpace;
 
 fn main() {
-    
-    let outpath = "traced.bmp";
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match cli::parse(&args) {
+        Ok(Command::Render(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n", err);
+            eprintln!("run with --help for usage");
+            std::process::exit(2);
+        }
+    };
+    if let Err(err) = render(*options) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+/// the camera and settings of the built-in random spheres scene
+fn default_description() -> SceneDescription {
+    SceneDescription {
+        image: ImageDescription { width: 800, height: 450 },
+        camera: CameraDescription {
+            origin: [13.0, 2.0, 3.0],
+            target: [0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            projection: ProjectionDescription::Perspective,
+            vfov: 20.0,
+            aperture: 0.1,
+            focus_distance: Some(10.0),
+        },
+        renderer: RendererDescription::default(),
+        textures: Default::default(),
+        materials: Default::default(),
+        objects: Vec::new(),
+        environment: None,
+        base_dir: Default::default(),
+    }
+}
+
+fn apply_overrides(description:&mut SceneDescription, options:&Options) -> Result<(), cli::CliError> {
+    let overrides = &options.camera;
+    let camera = &mut description.camera;
+    match overrides.projection {
+        // keep the settings of a scene that already uses the projection
+        Some(projection) if discriminant(&projection) != discriminant(&camera.projection) => camera.projection = projection,
+        _ => (),
+    }
+    if overrides.view_width.is_some() || overrides.view_height.is_some() {
+        camera.projection = ProjectionDescription::Orthographic {
+            width: overrides.view_width,
+            height: overrides.view_height,
+        };
+    }
+    if overrides.fisheye_fov.is_some() || overrides.fisheye_mapping.is_some() {
+        if !matches!(camera.projection, ProjectionDescription::Fisheye { .. }) {
+            camera.projection = ProjectionDescription::from_name("fisheye").unwrap();
+        }
+        if let ProjectionDescription::Fisheye { fov, mapping } = &mut camera.projection {
+            *fov = overrides.fisheye_fov.unwrap_or(*fov);
+            *mapping = overrides.fisheye_mapping.unwrap_or(*mapping);
+        }
+    }
+
+    // panoramas keep the shape they are made for
+    let aspect = camera.projection.aspect_ratio().unwrap_or(description.aspect_ratio());
+    let image = &mut description.image;
+    match (options.width, options.height) {
+        (Some(width), Some(height)) => {
+            image.width = width;
+            image.height = height;
+        }
+        (Some(width), None) => {
+            image.width = width;
+            image.height = usize::max(1, (width as f32 / aspect) as usize);
+        }
+        (None, Some(height)) => {
+            image.height = height;
+            image.width = usize::max(1, (height as f32 * aspect) as usize);
+        }
+        (None, None) => (),
+    }
+
+    let renderer = &mut description.renderer;
+    renderer.samples = options.samples.unwrap_or(renderer.samples);
+    renderer.max_depth = options.max_depth.unwrap_or(renderer.max_depth);
+    renderer.integrator = options.integrator.unwrap_or(renderer.integrator);
+    renderer.denoiser = options.denoiser.unwrap_or(renderer.denoiser);
+    renderer.sampler = options.sampler.unwrap_or(renderer.sampler);
+    if let Some(filter) = options.filter {
+        // the scene's radius was meant for its own filter
+        renderer.filter = FilterDescription { filter, radius: None };
+    }
+    renderer.filter.radius = options.filter_radius.or(renderer.filter.radius);
+    renderer.adaptive = match (options.adaptive, options.min_samples) {
+        (Some(None), _) => None,
+        (None, None) => renderer.adaptive,
+        (threshold, min_samples) => {
+            let mut adaptive = renderer.adaptive.unwrap_or_default();
+            adaptive.threshold = threshold.flatten().unwrap_or(adaptive.threshold);
+            adaptive.min_samples = min_samples.unwrap_or(adaptive.min_samples);
+            Some(adaptive)
+        }
+    };
+    renderer.seed = options.seed.unwrap_or(renderer.seed);
+    renderer.exposure = options.exposure.unwrap_or(renderer.exposure);
+    renderer.tone_mapping = options.tone_mapping.unwrap_or(renderer.tone_mapping);
+
+    let camera = &mut description.camera;
+    camera.origin = overrides.origin.unwrap_or(camera.origin);
+    camera.target = overrides.target.unwrap_or(camera.target);
+    camera.up = overrides.up.unwrap_or(camera.up);
+    camera.vfov = overrides.vfov.unwrap_or(camera.vfov);
+    camera.aperture = overrides.aperture.unwrap_or(camera.aperture);
+    camera.focus_distance = overrides.focus_distance.or(camera.focus_distance);
+
+    let origin = Vector3::from(camera.origin);
+    let view = Vector3::from(camera.target) - origin;
+    if view.magnitude2() == 0.0 {
+        return Err(cli::CliError::new("camera origin and target must differ"));
+    }
+    if view.cross(Vector3::from(camera.up)).magnitude2() == 0.0 {
+        return Err(cli::CliError::new("camera up vector must not be parallel to the view direction"));
+    }
+    Ok(())
+}
+
+fn render(options:Options) -> Result<(), Box<dyn std::error::Error>> {
+    let mut description = match &options.scene {
+        Some(path) => SceneDescription::load(path)?,
+        None => default_description(),
+    };
+    apply_overrides(&mut description, &options)?;
+
+    let mut scene = Scene::new();
+    match &options.scene {
+        Some(_) => description.populate(&mut scene)?,
+        None => utils::test_scene_setup(&mut scene),
+    }
+    scene.build_bvh();
+
+    let outpath = options.output.as_str();
 
     println!("Raytracer In a Weekend!");
     println!("output: {}", outpath);
 
-    let aspect = 16.0 / 9.0;
-    let width = 800;
-    let height = (width as f32 / aspect) as usize;
-
-    let origin = Vector3::new(13.0, 2.0, 3.0);
-    let target = Vector3::new(0.0, 0.0, 0.0);
-    let up = Vector3::new(0.0, 1.0, 0.0);
-    let vfov = 20.0;
-    let aperature = 0.1;
+    let width = description.image.width;
+    let height = description.image.height;
 
-    // update target to get focus distance of 10
-    let target = 10.0 * (target-origin).normalize() + origin;
+    let renderer = description.renderer();
+    let denoiser = description.renderer.denoiser.map(|denoiser| denoiser.build());
 
-    let renderer = Arc::new(Renderer::new(32, 32));
-    let img = Arc::new(Image::new(width, height));
-    let scene = Arc::new(RwLock::new(Scene::new()));
-    let camera = Arc::new(Camera::new(origin, target, up, vfov, aspect, aperature));
+    // the denoiser needs its guides even if they aren't written out
+    let mut aovs = options.aovs.clone();
+    if denoiser.is_some() {
+        aovs.extend(AtrousFilter::GUIDES);
+    }
+    let full = Region { x: 0, y: 0, width, height };
+    let mut target = RenderTarget::new(width, height, full, &aovs);
+    let camera = description.camera();
+
+    let mut driver = RenderDriver::new()
+        .with_tile_size(options.tile_size)
+        .with_tile_order(options.tile_order);
+    if let Some(nthreads) = options.threads {
+        driver = driver.with_threads(nthreads);
+    }
 
-    {
-        let mut scene_locked = scene.write().unwrap();
-        utils::test_scene_setup(&mut scene_locked);
+    let mut session = RenderSession::new().with_progress( |progress| {
+        let eta = progress.eta.map_or(String::from("-"), |eta| format!("{:.1} s", eta.as_secs_f32()));
+        println!("\rprogress: {:.2}%, eta: {}", 100.0 * progress.fraction(), eta);
+    });
+    if let Some(limit) = options.time_limit {
+        session = session.with_time_budget(limit);
     }
 
     println!("running...");
+    println!("parallelism: {}", driver.threads());
+    println!("njobs: {}", driver.tiles(&target).len());
     let timer = Instant::now();
 
-    run(&renderer, &camera, &scene, &img);
+    match options.progressive {
+        None => driver.render(&renderer, camera.as_ref(), &scene, &target, &session),
+        Some(pass_samples) => {
+            let nsamples = renderer.nsamples();
+            let mut snapshot = Ok(());
+            driver.render_progressive(&renderer, camera.as_ref(), &scene, &target, &session, pass_samples, |samples, target| {
+                println!("pass: {}/{} samples, {} ms", samples, nsamples, timer.elapsed().as_millis());
+                // the last pass is written below, after denoising
+                if samples < nsamples {
+                    snapshot = target.write_with_aovs(outpath, options.format, &options.aovs);
+                }
+                snapshot.is_ok()
+            });
+            snapshot.map_err(|err| format!("couldn't write {}: {}", outpath, err))?;
+        }
+    }
     let elapsed = timer.elapsed().as_millis();
-
-    img.write_bmp(outpath);
-    println!("done! render time: {} ms", elapsed);   
-}
-
-fn run(renderer:&Arc<Renderer>, camera:&Arc<Camera>, scene:&Arc<RwLock<Scene>>, img:&Arc<Image>) {
-    let regions = Region{
-        x: 0, y: 0,
-        width: img.width(),
-        height: img.height(),
-    }.chunks(64);
-
-    let nthreads = std::thread::available_parallelism().unwrap().get();
-    let pool = ThreadPool::new(nthreads);
-    let njobs = regions.len();
-
-    println!("parallelism: {}", nthreads);
-    println!("njobs: {}", njobs);
-
-    let (tx, rx) = std::sync::mpsc::channel();
-
-    for region in regions
-    {
-        let tx = tx.clone();
-        let scene = scene.clone();
-        let camera = camera.clone();
-        let renderer = renderer.clone();
-
-        let target = RenderTarget {
-            full_width: img.width(),
-            full_height: img.height(),
-            buffer: Image::new_with_region(region),
-        };
-        pool.execute(move|| {
-            let scene_readonly = scene.read().unwrap();
-            renderer.render(&camera, &scene_readonly, &target);
-            tx.send(target.buffer).unwrap();
-        });
+    if let Some(StopReason::OutOfTime) = session.stop_reason() {
+        println!("time limit reached after {} samples", session.progress().samples);
     }
 
-    for i in 0..njobs {
-        let partial = rx.recv().unwrap();
-        img.blit(&partial);
-        println!("\rprogress: {:.2}%", 100.0 * (i+1) as f32 / njobs as f32);
+    if let Some(denoiser) = denoiser {
+        let timer = Instant::now();
+        target.buffer = denoiser.denoise(&target);
+        renderer.develop(&target.buffer);
+        println!("denoise time: {} ms", timer.elapsed().as_millis());
     }
+    target.aovs.retain(|aov, _| options.aovs.contains(aov));
+
+    target.write(outpath, options.format)
+        .map_err(|err| format!("couldn't write {}: {}", outpath, err))?;
+    println!("done! render time: {} ms", elapsed);
+    Ok(())
 }
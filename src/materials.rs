@@ -2,27 +2,63 @@
 use crate::ray::Ray;
 use crate::image::Color;
 use crate::hittables::HitRecord;
-use crate::randlut::random_unit_vector3;
+use crate::textures::Texture;
+use crate::textures::SolidColor;
+use crate::sampler::Sampler;
+use crate::sampler::sample_unit_sphere;
 
 use cgmath::AbsDiffEq;
 use std::sync::Arc;
-use rand::Rng;
+use std::f32::consts::FRAC_1_PI;
 
 type Vector3 = cgmath::Vector3<f32>;
 
 pub struct Scattered {
     pub attenuation: Color,
     pub ray: Ray,
+    /// solid angle pdf of the scattered direction, None for specular
+    /// scattering which can't be combined with light sampling
+    pub pdf: Option<f32>,
 }
 
 impl Scattered {
     pub fn new(ray:Ray, attenuation:Color) -> Option<Scattered> {
-        Some( Scattered{ attenuation,  ray } )
+        Some( Scattered{ attenuation,  ray, pdf: None } )
+    }
+
+    pub fn new_with_pdf(ray:Ray, attenuation:Color, pdf:f32) -> Option<Scattered> {
+        Some( Scattered{ attenuation,  ray, pdf: Some(pdf) } )
     }
 }
 
 pub trait Material: Sync+Send {
-    fn scatter(&self, ray:&Ray, hit:&HitRecord) -> Option<Scattered>;
+    fn scatter(&self, ray:&Ray, hit:&HitRecord, sampler:&mut dyn Sampler) -> Option<Scattered>;
+
+    /// radiance given off at the hit point, nothing by default
+    fn emitted(&self, _hit:&HitRecord) -> Color {
+        Color::black()
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// overall reflectance at the hit, the color the surface would have
+    /// under uniform white light. Used for albedo passes and denoising.
+    fn albedo(&self, _hit:&HitRecord) -> Color {
+        Color::black()
+    }
+
+    /// BSDF times the cosine term for light arriving from `direction`,
+    /// only needed by materials that scatter with a pdf
+    fn eval(&self, _ray:&Ray, _hit:&HitRecord, _direction:Vector3) -> Color {
+        Color::black()
+    }
+
+    /// pdf of `scatter` choosing `direction`
+    fn pdf(&self, _ray:&Ray, _hit:&HitRecord, _direction:Vector3) -> f32 {
+        0.0
+    }
 }
 
 fn equal(a:&Vector3, b:&Vector3) -> bool {
@@ -42,45 +78,67 @@ fn refract(uv:Vector3, n:Vector3, ratio:f32) -> Vector3 {
 }
 
 pub struct Lambertian {
-    pub albedo:Color,
+    pub albedo:Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo:Color) -> Arc<Lambertian> {
+        Lambertian::new_textured(SolidColor::new(albedo))
+    }
+
+    pub fn new_textured(albedo:Arc<dyn Texture>) -> Arc<Lambertian> {
         Arc::new(Lambertian{albedo})
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray:&Ray, hit:&HitRecord) -> Option<Scattered> {
-        let perturbation = random_unit_vector3();
-        let scatter_dir = match equal(&hit.normal, &-perturbation) {
-            false => hit.normal + perturbation,
-            true  => hit.normal,
+    fn scatter(&self, ray:&Ray, hit:&HitRecord, sampler:&mut dyn Sampler) -> Option<Scattered> {
+        let perturbation = sample_unit_sphere(sampler.next_2d());
+        let scatter_dir = match equal(&hit.shading_normal, &-perturbation) {
+            false => hit.shading_normal + perturbation,
+            true  => hit.shading_normal,
         };
-        Scattered::new(
-            Ray::new(hit.point, scatter_dir), 
-            self.albedo,
-        )
+        let scattered = Ray::new(hit.point, scatter_dir);
+        let pdf = self.pdf(ray, hit, scattered.direction());
+        let albedo = self.albedo.value(hit.uv, hit.point);
+        Scattered::new_with_pdf(scattered, albedo, pdf)
+    }
+
+    fn albedo(&self, hit:&HitRecord) -> Color {
+        self.albedo.value(hit.uv, hit.point)
+    }
+
+    fn eval(&self, _ray:&Ray, hit:&HitRecord, direction:Vector3) -> Color {
+        let cosine = f32::max(cgmath::dot(hit.shading_normal, direction), 0.0);
+        (cosine * FRAC_1_PI) * self.albedo.value(hit.uv, hit.point)
+    }
+
+    fn pdf(&self, _ray:&Ray, hit:&HitRecord, direction:Vector3) -> f32 {
+        // normal plus a random unit vector is cosine distributed
+        f32::max(cgmath::dot(hit.shading_normal, direction), 0.0) * FRAC_1_PI
     }
 }
 
 pub struct Metal {
-    pub albedo:Color,
+    pub albedo:Arc<dyn Texture>,
     pub roughness:f32,
 }
 
 impl Metal {
     pub fn new(albedo:Color, roughness:f32) -> Arc<Metal> {
+        Metal::new_textured(SolidColor::new(albedo), roughness)
+    }
+
+    pub fn new_textured(albedo:Arc<dyn Texture>, roughness:f32) -> Arc<Metal> {
         let roughness = roughness.clamp(0.0, 1.0);
         Arc::new(Metal{albedo, roughness})
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray:&Ray, hit:&HitRecord) -> Option<Scattered> {
-        let reflection = reflect(ray.direction(), hit.normal);
-        let perturbation = random_unit_vector3();
+    fn scatter(&self, ray:&Ray, hit:&HitRecord, sampler:&mut dyn Sampler) -> Option<Scattered> {
+        let reflection = reflect(ray.direction(), hit.shading_normal);
+        let perturbation = sample_unit_sphere(sampler.next_2d());
 
         let reflection = match equal(&reflection, &-perturbation) {
             false => reflection + self.roughness * perturbation,
@@ -88,9 +146,13 @@ impl Material for Metal {
         };
         Scattered::new(
             Ray::new(hit.point, reflection), 
-            self.albedo,
+            self.albedo.value(hit.uv, hit.point),
         )
     }
+
+    fn albedo(&self, hit:&HitRecord) -> Color {
+        self.albedo.value(hit.uv, hit.point)
+    }
 }
 
 pub struct Dialectric {
@@ -104,13 +166,13 @@ impl Dialectric {
 }
 
 impl Material for Dialectric {
-    fn scatter(&self, ray:&Ray, hit:&HitRecord) -> Option<Scattered> {
+    fn scatter(&self, ray:&Ray, hit:&HitRecord, sampler:&mut dyn Sampler) -> Option<Scattered> {
         let attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = match hit.front_face {
             true => 1.0/self.refraction_index,
             false => self.refraction_index,
         };
-        let cos_theta = f32::min(cgmath::dot(-ray.direction(), hit.normal), 1.0);
+        let cos_theta = f32::min(cgmath::dot(-ray.direction(), hit.shading_normal), 1.0);
         let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
 
         let reflectance = |cosine:f32, index:f32| {
@@ -118,19 +180,50 @@ impl Material for Dialectric {
             let r0 = r0*r0;
             r0 + (1.0-r0)*(1.0-cosine).powf(5.0)
         };
-        let chance_reflect = || {
-            let mut rng = rand::thread_rng();
-            reflectance(cos_theta, refraction_ratio) > rng.gen_range(0.0..1.0)
-        };
+        // drawn even when refraction is impossible so the dimensions line up
+        let chance_reflect = reflectance(cos_theta, refraction_ratio) > sampler.next_1d();
         let cannot_refract = (refraction_ratio * sin_theta) > 1.0;
         
-        let direction = match cannot_refract || chance_reflect() {
-            true  => reflect(ray.direction(), hit.normal),
-            false => refract(ray.direction(), hit.normal, refraction_ratio),
+        let direction = match cannot_refract || chance_reflect {
+            true  => reflect(ray.direction(), hit.shading_normal),
+            false => refract(ray.direction(), hit.shading_normal, refraction_ratio),
         };
         Scattered::new(
             Ray::new(hit.point, direction), 
             attenuation,
         )
     }
+
+    /// clear glass passes on all light
+    fn albedo(&self, _hit:&HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+}
+
+pub struct DiffuseLight {
+    pub emit:Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit:Color) -> Arc<DiffuseLight> {
+        DiffuseLight::new_textured(SolidColor::new(emit))
+    }
+
+    pub fn new_textured(emit:Arc<dyn Texture>) -> Arc<DiffuseLight> {
+        Arc::new(DiffuseLight{emit})
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray:&Ray, _hit:&HitRecord, _sampler:&mut dyn Sampler) -> Option<Scattered> {
+        None
+    }
+
+    fn emitted(&self, hit:&HitRecord) -> Color {
+        self.emit.value(hit.uv, hit.point)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use cgmath::InnerSpace;
+
+use crate::scene::Scene;
+use crate::image::Color;
+use crate::hittables::TriangleMesh;
+use crate::materials::Material;
+use crate::materials::Metal;
+use crate::materials::Lambertian;
+use crate::materials::Dialectric;
+use crate::materials::DiffuseLight;
+
+type Vector2 = cgmath::Vector2<f32>;
+type Vector3 = cgmath::Vector3<f32>;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    UnknownMaterial {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => {
+                write!(f, "couldn't read {}: {}", path.display(), source)
+            }
+            ObjError::Parse { path, line, message } => {
+                write!(f, "{}:{}: {}", path.display(), line, message)
+            }
+            ObjError::UnknownMaterial { path, line, name } => {
+                write!(f, "{}:{}: unknown material '{}'", path.display(), line, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// One triangle mesh per group and material used within that group
+pub struct ObjGroup {
+    pub name: String,
+    pub material_name: Option<String>,
+    pub mesh: Box<TriangleMesh>,
+}
+
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+}
+
+impl ObjModel {
+    pub fn num_triangles(&self) -> usize {
+        self.groups.iter().map(|g| g.mesh.num_triangles()).sum()
+    }
+
+    pub fn push_into(self, scene:&mut Scene) {
+        for group in self.groups {
+            scene.push(group.mesh);
+        }
+    }
+}
+
+/// Loads a wavefront .obj file along with any .mtl libraries it references,
+/// mtl paths are resolved relative to the obj file.
+pub fn load_obj<P: AsRef<Path>>(path:P) -> Result<ObjModel, ObjError> {
+    let default_material = Lambertian::new(Color::new(0.8, 0.8, 0.8));
+    load_obj_with_default(path, default_material)
+}
+
+/// Same as `load_obj`, faces without a `usemtl` get `default_material`
+pub fn load_obj_with_default<P: AsRef<Path>>(path:P, default_material:Arc<dyn Material>) -> Result<ObjModel, ObjError> {
+    let path = path.as_ref();
+    let mut parser = ObjParser::new(path, default_material);
+    for (index, line) in read_lines(path)?.enumerate() {
+        let line = line.map_err(|source| ObjError::Io { path: path.to_path_buf(), source })?;
+        parser.line = index + 1;
+        parser.parse_line(&line)?;
+    }
+    Ok(parser.finish())
+}
+
+/// Parses a .mtl material library into materials keyed by name
+pub fn load_mtl<P: AsRef<Path>>(path:P) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let path = path.as_ref();
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlParams)> = None;
+
+    for (index, line) in read_lines(path)?.enumerate() {
+        let line = line.map_err(|source| ObjError::Io { path: path.to_path_buf(), source })?;
+        let ctx = Context { path, line: index + 1 };
+        let mut tokens = tokenize(&line);
+        let keyword = match tokens.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+        if keyword == "newmtl" {
+            if let Some((name, params)) = current.take() {
+                materials.insert(name, params.to_material());
+            }
+            let name = rest_of_line(&line, keyword);
+            if name.is_empty() {
+                return Err(ctx.error("newmtl without a name"));
+            }
+            current = Some((name.to_string(), MtlParams::default()));
+            continue;
+        }
+        let params = match &mut current {
+            None => continue,
+            Some((_, params)) => params,
+        };
+        match keyword {
+            "Kd" => params.kd = ctx.parse_color(&mut tokens)?,
+            "Ks" => params.ks = ctx.parse_color(&mut tokens)?,
+            "Ke" => params.ke = ctx.parse_color(&mut tokens)?,
+            "Ni" => params.ni = ctx.parse_f32(tokens.next())?,
+            "Ns" => params.ns = ctx.parse_f32(tokens.next())?,
+            "d"  => params.d = ctx.parse_f32(tokens.next())?,
+            "Tr" => params.d = 1.0 - ctx.parse_f32(tokens.next())?,
+            "illum" => params.illum = ctx.parse_index(tokens.next())?,
+            // everything else (texture maps, Ka, Tf, ...) is not supported yet
+            _ => (),
+        }
+    }
+    if let Some((name, params)) = current.take() {
+        materials.insert(name, params.to_material());
+    }
+    Ok(materials)
+}
+
+fn read_lines(path:&Path) -> Result<std::io::Lines<BufReader<File>>, ObjError> {
+    match File::open(path) {
+        Err(source) => Err(ObjError::Io { path: path.to_path_buf(), source }),
+        Ok(file) => Ok(BufReader::new(file).lines()),
+    }
+}
+
+fn tokenize(line:&str) -> std::str::SplitWhitespace<'_> {
+    let line = match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    line.split_whitespace()
+}
+
+/// names (groups, materials, files) may contain spaces
+fn rest_of_line<'a>(line:&'a str, keyword:&str) -> &'a str {
+    let line = match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    line.trim_start()[keyword.len()..].trim()
+}
+
+struct Context<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl Context<'_> {
+    fn error(&self, message:&str) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    fn parse_f32(&self, token:Option<&str>) -> Result<f32, ObjError> {
+        let token = token.ok_or_else(|| self.error("expected a number"))?;
+        token.parse().map_err(|_| self.error(&format!("invalid number '{}'", token)))
+    }
+
+    fn parse_index(&self, token:Option<&str>) -> Result<usize, ObjError> {
+        let token = token.ok_or_else(|| self.error("expected an integer"))?;
+        token.parse().map_err(|_| self.error(&format!("invalid integer '{}'", token)))
+    }
+
+    fn parse_color<'b>(&self, tokens:&mut impl Iterator<Item=&'b str>) -> Result<Color, ObjError> {
+        let red = self.parse_f32(tokens.next())?;
+        // a single value means grey
+        let green = match tokens.next() {
+            None => return Ok(Color::new(red, red, red)),
+            token => self.parse_f32(token)?,
+        };
+        let blue = self.parse_f32(tokens.next())?;
+        Ok(Color::new(red, green, blue))
+    }
+}
+
+struct MtlParams {
+    kd: Color,
+    ks: Color,
+    ke: Color,
+    ni: f32,
+    ns: f32,
+    d: f32,
+    illum: usize,
+}
+
+impl Default for MtlParams {
+    fn default() -> Self {
+        MtlParams {
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: Color::black(),
+            ke: Color::black(),
+            ni: 1.0,
+            ns: 0.0,
+            d: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlParams {
+    /// maps the illumination model onto the closest of our materials:
+    /// emissive materials become lights, transparent models become glass,
+    /// reflective models become metal, and everything else is diffuse
+    fn to_material(&self) -> Arc<dyn Material> {
+        let emissive = self.ke.red > 0.0 || self.ke.green > 0.0 || self.ke.blue > 0.0;
+        let transparent = self.d < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9);
+        let reflective = matches!(self.illum, 3 | 5 | 8);
+        if emissive {
+            DiffuseLight::new(self.ke)
+        } else if transparent {
+            let ior = match self.ni > 1.0 {
+                true => self.ni,
+                false => 1.5,
+            };
+            Dialectric::new(ior)
+        } else if reflective {
+            // Phong exponents run from 0 (rough) to 1000 (mirror)
+            let roughness = (2.0 / (self.ns + 2.0)).sqrt();
+            Metal::new(self.ks, roughness)
+        } else {
+            Lambertian::new(self.kd)
+        }
+    }
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<Vector3>,
+    normals: Vec<Option<Vector3>>,
+    uvs: Vec<Option<Vector2>>,
+    indices: Vec<[usize; 3]>,
+    vertex_map: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+}
+
+struct ObjParser<'a> {
+    path: &'a Path,
+    line: usize,
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    uvs: Vec<Vector2>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    default_material: Arc<dyn Material>,
+    group: String,
+    material_name: Option<String>,
+    // meshes in the order they were first used, keyed by group and material
+    meshes: Vec<((String, Option<String>), MeshBuilder)>,
+    mesh_lookup: HashMap<(String, Option<String>), usize>,
+}
+
+impl<'a> ObjParser<'a> {
+
+    fn new(path:&'a Path, default_material:Arc<dyn Material>) -> Self {
+        ObjParser {
+            path,
+            line: 0,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            materials: HashMap::new(),
+            default_material,
+            group: "default".to_string(),
+            material_name: None,
+            meshes: Vec::new(),
+            mesh_lookup: HashMap::new(),
+        }
+    }
+
+    fn context(&self) -> Context<'a> {
+        Context { path: self.path, line: self.line }
+    }
+
+    fn parse_line(&mut self, line:&str) -> Result<(), ObjError> {
+        let ctx = self.context();
+        let mut tokens = tokenize(line);
+        let keyword = match tokens.next() {
+            None => return Ok(()),
+            Some(keyword) => keyword,
+        };
+        match keyword {
+            "v" => {
+                let x = ctx.parse_f32(tokens.next())?;
+                let y = ctx.parse_f32(tokens.next())?;
+                let z = ctx.parse_f32(tokens.next())?;
+                self.positions.push(Vector3::new(x, y, z));
+            }
+            "vn" => {
+                let x = ctx.parse_f32(tokens.next())?;
+                let y = ctx.parse_f32(tokens.next())?;
+                let z = ctx.parse_f32(tokens.next())?;
+                self.normals.push(Vector3::new(x, y, z));
+            }
+            "vt" => {
+                let u = ctx.parse_f32(tokens.next())?;
+                let v = match tokens.next() {
+                    None => 0.0,
+                    token => ctx.parse_f32(token)?,
+                };
+                self.uvs.push(Vector2::new(u, v));
+            }
+            "f" => {
+                let corners = tokens.map(|token| self.parse_corner(&ctx, token))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(ctx.error("face needs at least 3 vertices"));
+                }
+                self.push_face(&corners);
+            }
+            "g" | "o" => {
+                let name = rest_of_line(line, keyword);
+                self.group = match name.is_empty() {
+                    true => "default".to_string(),
+                    false => name.to_string(),
+                };
+            }
+            "usemtl" => {
+                let name = rest_of_line(line, keyword);
+                if !self.materials.contains_key(name) {
+                    return Err(ObjError::UnknownMaterial {
+                        path: self.path.to_path_buf(),
+                        line: self.line,
+                        name: name.to_string(),
+                    });
+                }
+                self.material_name = Some(name.to_string());
+            }
+            "mtllib" => {
+                let dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+                // several libraries may be listed on one line
+                for file in tokens {
+                    let materials = load_mtl(dir.join(file))?;
+                    self.materials.extend(materials);
+                }
+            }
+            // smoothing groups, lines, points, curves, ... are ignored
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// resolves one `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner to zero based indices
+    fn parse_corner(&self, ctx:&Context, token:&str) -> Result<(usize, Option<usize>, Option<usize>), ObjError> {
+        let resolve = |field:Option<&str>, count:usize, what:&str| -> Result<Option<usize>, ObjError> {
+            let field = match field {
+                None | Some("") => return Ok(None),
+                Some(field) => field,
+            };
+            let index: i64 = field.parse()
+                .map_err(|_| ctx.error(&format!("invalid {} index '{}'", what, field)))?;
+            // indices are one based, negative ones count back from the latest
+            let resolved = match index {
+                0 => None,
+                i if i > 0 => Some(i as usize - 1),
+                i => (count as i64).checked_add(i).filter(|&i| i >= 0).map(|i| i as usize),
+            };
+            match resolved {
+                Some(i) if i < count => Ok(Some(i)),
+                _ => Err(ctx.error(&format!("{} index {} out of range", what, index))),
+            }
+        };
+        let mut fields = token.split('/');
+        let position = resolve(fields.next(), self.positions.len(), "vertex")?
+            .ok_or_else(|| ctx.error("face corner without a vertex index"))?;
+        let uv = resolve(fields.next(), self.uvs.len(), "texture coordinate")?;
+        let normal = resolve(fields.next(), self.normals.len(), "normal")?;
+        Ok((position, uv, normal))
+    }
+
+    fn push_face(&mut self, corners:&[(usize, Option<usize>, Option<usize>)]) {
+        let points: Vec<Vector3> = corners.iter().map(|c| self.positions[c.0]).collect();
+        let triangles = triangulate(&points);
+
+        let key = (self.group.clone(), self.material_name.clone());
+        let mesh_index = match self.mesh_lookup.get(&key) {
+            Some(&index) => index,
+            None => {
+                self.meshes.push((key.clone(), MeshBuilder::default()));
+                self.mesh_lookup.insert(key, self.meshes.len()-1);
+                self.meshes.len()-1
+            }
+        };
+        let mesh = &mut self.meshes[mesh_index].1;
+
+        let local: Vec<usize> = corners.iter().map( |&(p, t, n)| {
+            *mesh.vertex_map.entry((p, t, n)).or_insert_with(|| {
+                mesh.positions.push(self.positions[p]);
+                mesh.uvs.push(t.map(|t| self.uvs[t]));
+                mesh.normals.push(n.map(|n| self.normals[n]));
+                mesh.positions.len()-1
+            })
+        }).collect();
+
+        for [a, b, c] in triangles {
+            mesh.indices.push([local[a], local[b], local[c]]);
+        }
+    }
+
+    fn finish(self) -> ObjModel {
+        let groups = self.meshes.into_iter().map( |((name, material_name), mesh)| {
+            let material = match &material_name {
+                None => self.default_material.clone(),
+                Some(material_name) => self.materials[material_name].clone(),
+            };
+            // the mesh can only use normals and uvs if every vertex has them
+            let normals = mesh.normals.into_iter().collect::<Option<Vec<_>>>().unwrap_or_default();
+            let uvs = mesh.uvs.into_iter().collect::<Option<Vec<_>>>().unwrap_or_default();
+            let mesh = TriangleMesh::new(mesh.positions, normals, uvs, mesh.indices, material);
+            ObjGroup { name, material_name, mesh }
+        }).collect();
+        ObjModel { groups }
+    }
+}
+
+/// Splits a planar polygon into triangles by ear clipping in the plane of
+/// the polygon, which also handles concave faces. Falls back to a fan if
+/// the polygon is degenerate or self intersecting.
+fn triangulate(points:&[Vector3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+    let fan = || (1..n-1).map(|i| [0, i, i+1]).collect();
+
+    // Newell's method gives a robust normal for non-planar polygons
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+        let a = points[i];
+        let b = points[(i+1) % n];
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+    }
+    if normal.magnitude2() == 0.0 {
+        return fan();
+    }
+
+    // project onto the plane most perpendicular to the normal
+    let (ax, ay) = match (normal.x.abs(), normal.y.abs(), normal.z.abs()) {
+        (x, y, z) if x >= y && x >= z => (1, 2),
+        (_, y, z) if y >= z => (2, 0),
+        _ => (0, 1),
+    };
+    let flip = normal[3 - ax - ay] < 0.0;
+    let p2: Vec<Vector2> = points.iter().map( |p| match flip {
+        false => Vector2::new(p[ax], p[ay]),
+        true  => Vector2::new(p[ay], p[ax]),
+    }).collect();
+
+    let cross = |a:Vector2, b:Vector2, c:Vector2| (b.x-a.x)*(c.y-a.y) - (b.y-a.y)*(c.x-a.x);
+    let inside = |p:Vector2, a:Vector2, b:Vector2, c:Vector2| {
+        cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+    };
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n-2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find( |&i| {
+            let (ia, ib, ic) = (remaining[(i+m-1) % m], remaining[i], remaining[(i+1) % m]);
+            let (a, b, c) = (p2[ia], p2[ib], p2[ic]);
+            if cross(a, b, c) <= 0.0 {
+                return false; // reflex or degenerate corner
+            }
+            remaining.iter()
+                .filter(|&&j| j != ia && j != ib && j != ic)
+                .all(|&j| !inside(p2[j], a, b, c))
+        });
+        let i = match ear {
+            None => return fan(),
+            Some(i) => i,
+        };
+        triangles.push([remaining[(i+m-1) % m], remaining[i], remaining[(i+1) % m]]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
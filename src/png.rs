@@ -0,0 +1,125 @@
+// minimal png encoder, 8 bit truecolor with or without alpha
+
+use crate::deflate;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = match c & 1 {
+                1 => 0xedb88320 ^ (c >> 1),
+                _ => c >> 1,
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// crc32 as used by png and zip
+pub fn crc32(bytes:&[u8]) -> u32 {
+    let mut c = 0xffffffffu32;
+    for &b in bytes {
+        c = CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    c ^ 0xffffffff
+}
+
+fn write_chunk(out:&mut Vec<u8>, kind:&[u8; 4], data:&[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+#[inline]
+fn paeth(a:u8, b:u8, c:u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// applies filter `kind` to `row` given the unfiltered row above it
+fn filter_row(kind:u8, row:&[u8], above:&[u8], bpp:usize, out:&mut Vec<u8>) {
+    out.clear();
+    out.push(kind);
+    for i in 0..row.len() {
+        let a = match i >= bpp { true => row[i-bpp], false => 0 };
+        let b = above[i];
+        let c = match i >= bpp { true => above[i-bpp], false => 0 };
+        let predicted = match kind {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+/// encodes top down, tightly packed rgb or rgba rows
+pub fn encode(width:usize, height:usize, alpha:bool, pixels:&[u8]) -> Vec<u8> {
+    let bpp = match alpha { true => 4, false => 3 };
+    let stride = bpp * width;
+    assert_eq!(pixels.len(), stride * height, "png pixel data doesn't match the image size");
+
+    // each row picks the filter with the smallest sum of absolute
+    // differences, the usual heuristic from the png spec
+    let mut filtered = Vec::with_capacity((stride + 1) * height);
+    let zeros = vec![0u8; stride];
+    let mut candidate = Vec::with_capacity(stride + 1);
+    let mut best = Vec::with_capacity(stride + 1);
+    for y in 0..height {
+        let row = &pixels[y*stride..(y+1)*stride];
+        let above = match y {
+            0 => &zeros[..],
+            _ => &pixels[(y-1)*stride..y*stride],
+        };
+        let mut best_cost = u64::MAX;
+        for kind in 0..5 {
+            filter_row(kind, row, above, bpp, &mut candidate);
+            let cost: u64 = candidate[1..].iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+            if cost < best_cost {
+                best_cost = cost;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+        filtered.extend_from_slice(&best);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.push(8);
+    header.push(match alpha { true => COLOR_TYPE_RGBA, false => COLOR_TYPE_RGB });
+    header.extend_from_slice(&[0, 0, 0]); // deflate, adaptive filtering, no interlace
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &deflate::zlib_compress(&filtered));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
@@ -1,107 +1,379 @@
+use std::sync::Arc;
+use std::ops::Range;
+use std::collections::BTreeMap;
 
-use crate::ray::Ray;
 use crate::image::Image;
 use crate::image::Color;
-use crate::image::Coloru8;
+use crate::image::Region;
+use crate::image::ExrLayer;
+use crate::image::ImageFormat;
+use crate::image::SampleStatistics;
 use crate::scene::Scene;
 use crate::camera::Camera;
-use crate::hittables::HitRecord;
+use crate::tonemap;
+use crate::tonemap::DisplayTransform;
+use crate::integrators::Integrator;
+use crate::integrators::PathTracer;
+use crate::integrators::TMIN;
+use crate::integrators::TMAX;
+use crate::aov::Aov;
+use crate::session::RenderSession;
+use crate::session::StopReason;
+use crate::sampler::SamplerType;
+use crate::filter::Filter;
+use crate::filter::BoxFilter;
+use crate::filter::FilterTable;
 
-use lazy_static::lazy_static;
-use rand::{Rng, SeedableRng};
-use rand::rngs::SmallRng;
-use cgmath::InnerSpace;
+pub struct Renderer {
+    nsamples: usize,
+    seed:u64,
+    display:DisplayTransform,
+    integrator:Arc<dyn Integrator>,
+    adaptive:Option<AdaptiveSampling>,
+    sampler:SamplerType,
+    filter:FilterTable,
+}
 
-lazy_static! {
-    static ref GAMMA_LUT:Vec<u8> = (0..256).map( |i| {
-        (255.0 * (i as f32 / 255.0).sqrt()) as u8
-    }).collect();
+/// Stops sampling a pixel once the standard error of its mean luminance,
+/// relative to the square root of that mean, falls below `threshold`. The
+/// square root keeps dark pixels from chasing noise too faint to see.
+/// The renderer's sample count is the most a pixel gets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// samples taken before the error is trusted, and between checks
+    pub min_samples: usize,
+    pub threshold: f32,
 }
 
-pub struct Renderer {
-    nsamples: usize,
-    max_depth: usize,
-    tmin:f32,
-    tmax:f32,
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        AdaptiveSampling { min_samples: 16, threshold: 0.01 }
+    }
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples:usize, threshold:f32) -> AdaptiveSampling {
+        AdaptiveSampling { min_samples, threshold }
+    }
+
+    /// estimated error of a pixel with these sample statistics
+    pub fn error(&self, statistics:&SampleStatistics) -> f32 {
+        statistics.variance_of_mean().sqrt() / statistics.mean.max(1e-4).sqrt()
+    }
+
+    pub fn converged(&self, statistics:&SampleStatistics) -> bool {
+        // the variance needs at least two samples
+        statistics.count >= self.min_samples.max(2) as f32 && self.error(statistics) < self.threshold
+    }
 }
 
 pub struct RenderTarget {
     pub full_width: usize,
     pub full_height: usize,
     pub buffer: Image,
+    /// auxiliary passes, each covering the same region as `buffer`
+    pub aovs: BTreeMap<Aov, Image>,
+}
+
+impl RenderTarget {
+
+    /// covers `region` of the full image, with an empty buffer for every pass in `aovs`
+    pub fn new(full_width:usize, full_height:usize, region:Region, aovs:&[Aov]) -> RenderTarget {
+        RenderTarget {
+            full_width,
+            full_height,
+            buffer: Image::new_with_region(region),
+            aovs: aovs.iter().map(|&aov| (aov, Image::new_with_region(region))).collect(),
+        }
+    }
+
+    /// copy of the image and passes inside `region`, so a tile can keep
+    /// accumulating samples into what was rendered before
+    pub fn crop(&self, region:Region) -> RenderTarget {
+        RenderTarget {
+            full_width: self.full_width,
+            full_height: self.full_height,
+            buffer: self.buffer.crop(region),
+            aovs: self.aovs.iter().map(|(aov, image)| (*aov, image.crop(region))).collect(),
+        }
+    }
+
+    /// copies the image and passes of a rendered tile into place
+    pub fn blit(&self, tile:&RenderTarget) {
+        self.buffer.blit(&tile.buffer);
+        for (aov, buffer) in &self.aovs {
+            if let Some(src) = tile.aovs.get(aov) {
+                buffer.blit(src);
+            }
+        }
+    }
+
+    /// adds the samples splatted into a tile to the image and passes,
+    /// tiles may overlap
+    pub fn accumulate(&self, tile:&RenderTarget) {
+        self.buffer.accumulate(&tile.buffer);
+        for (aov, buffer) in &self.aovs {
+            if let Some(src) = tile.aovs.get(aov) {
+                buffer.accumulate(src);
+            }
+        }
+    }
+
+    /// writes the image and its passes, OpenEXR images hold every pass as a
+    /// layer while other formats get a file per pass named like `traced.depth.png`
+    pub fn write(&self, path:&str, format:ImageFormat) -> std::io::Result<()> {
+        let aovs: Vec<Aov> = self.aovs.keys().copied().collect();
+        self.write_with_aovs(path, format, &aovs)
+    }
+
+    /// like `write` but only with those of the target's passes that are in `aovs`
+    pub fn write_with_aovs(&self, path:&str, format:ImageFormat, aovs:&[Aov]) -> std::io::Result<()> {
+        let passes = self.aovs.iter().filter(|(aov, _)| aovs.contains(aov));
+        if let ImageFormat::Exr(pixel_type) = format {
+            let layers: Vec<ExrLayer> = passes.map( |(aov, image)| ExrLayer {
+                name: aov.name(),
+                channels: aov.channels(),
+                pixel_type: aov.exr_pixel_type(pixel_type),
+                image,
+            }).collect();
+            return self.buffer.write_exr_with_layers(path, pixel_type, &layers);
+        }
+        self.buffer.write(path, format)?;
+        for (aov, image) in passes {
+            if !format.is_hdr() {
+                aov.to_display(image);
+            }
+            image.write(&pass_path(path, *aov), format)?;
+        }
+        Ok(())
+    }
+}
+
+/// inserts the name of the pass before the extension
+pub fn pass_path(path:&str, aov:Aov) -> String {
+    let path = std::path::Path::new(path);
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format!("{}.{}.{}", stem.to_string_lossy(), aov.name(), ext.to_string_lossy()),
+        _ => format!("{}.{}", path.file_name().unwrap_or_default().to_string_lossy(), aov.name()),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
 }
 
 impl Renderer {
 
+    /// path traces up to `max_depth` bounces unless given another integrator
     pub fn new(nsamples:usize, max_depth:usize) -> Self {
         Renderer {
             nsamples,
-            max_depth,
-            tmin: 0.001,
-            tmax: 1000.0,
+            seed: 0,
+            display: DisplayTransform::default(),
+            integrator: Arc::new(PathTracer::new(max_depth)),
+            adaptive: None,
+            sampler: SamplerType::default(),
+            filter: FilterTable::new(&BoxFilter::new(0.5)),
         }
     }
 
-    pub fn render(&self, camera:&Camera, scene:&Scene, target:&RenderTarget) {
+    pub fn with_seed(mut self, seed:u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// how the accumulated radiance is turned into the 8 bit image
+    pub fn with_display_transform(mut self, display:DisplayTransform) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// what estimates the radiance of each camera ray
+    pub fn with_integrator(mut self, integrator:Arc<dyn Integrator>) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    /// where the pixel, lens and path samples come from
+    pub fn with_sampler(mut self, sampler:SamplerType) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// how samples are weighted into the pixels around them, a box
+    /// covering just the pixel unless given another filter
+    pub fn with_filter(mut self, filter:Arc<dyn Filter>) -> Self {
+        self.filter = FilterTable::new(filter.as_ref());
+        self
+    }
+
+    /// takes fewer samples in pixels that have converged,
+    /// at most the renderer's sample count
+    pub fn with_adaptive_sampling(mut self, adaptive:AdaptiveSampling) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn display_transform(&self) -> DisplayTransform {
+        self.display
+    }
+
+    /// encodes the 8 bit pixels of `image` from its radiance, eg. after denoising
+    pub fn develop(&self, image:&Image) {
+        for (x, y) in image {
+            image.set_pixel_color_u8(x, y, self.display.to_u8(image.get_radiance(x, y)));
+        }
+    }
+
+    pub fn nsamples(&self) -> usize {
+        self.nsamples
+    }
+
+    /// how many pixels past its own a sample may land in
+    pub fn filter_margin(&self) -> usize {
+        ((self.filter.radius() + 0.5).ceil() as usize).saturating_sub(1)
+    }
+
+    /// takes every sample of every pixel of the target
+    pub fn render(&self, camera:&dyn Camera, scene:&Scene, target:&RenderTarget) {
+        self.render_samples(camera, scene, target, 0..self.nsamples, &RenderSession::new());
+    }
+
+    /// renders the whole target in passes of `pass_samples` samples per pixel.
+    /// After every pass `on_pass` gets the number of samples taken so far and
+    /// the target to take a snapshot of, returning false stops the render.
+    pub fn render_progressive<F>(&self, camera:&dyn Camera, scene:&Scene, target:&RenderTarget, pass_samples:usize, mut on_pass:F)
+    where F: FnMut(usize, &RenderTarget) -> bool
+    {
+        let pass_samples = pass_samples.max(1);
+        for start in (0..self.nsamples).step_by(pass_samples) {
+            let end = usize::min(start + pass_samples, self.nsamples);
+            self.render_samples(camera, scene, target, start..end, &RenderSession::new());
+            if !on_pass(end, target) {
+                break;
+            }
+        }
+    }
+
+    /// takes the samples in `samples` out of the renderer's `nsamples` and
+    /// adds them to what the target already holds. Rendering `0..n` and
+    /// then `n..nsamples` into the same target continues where the first
+    /// call stopped, which is how progressive passes are built.
+    /// Once `session` says to stop, a cancelled render returns right away
+    /// while one out of time still takes a sample in pixels that have none.
+    pub fn render_samples(&self, camera:&dyn Camera, scene:&Scene, target:&RenderTarget, samples:Range<usize>, session:&RenderSession) {
+        self.render_region(camera, scene, target, target.buffer.region(), samples, session);
+        self.develop(&target.buffer);
+    }
+
+    /// like `render_samples` but only for the pixels in `region`, with
+    /// samples splatted into whichever pixels of `target` the filter reaches.
+    /// They are gathered in a tile of their own and added to the target
+    /// once done, so threads can render neighbouring regions of the same
+    /// target. The 8 bit pixels are left to `develop`.
+    pub fn render_region(&self, camera:&dyn Camera, scene:&Scene, target:&RenderTarget, region:Region, samples:Range<usize>, session:&RenderSession) {
         let inv_w = 1.0 / (target.full_width-1) as f32;
         let inv_h = 1.0 / (target.full_height-1) as f32;
-        let scale = 255.0 / self.nsamples as f32;
-        
-        let mut rng = SmallRng::seed_from_u64(0);
-        let rands: Vec<(f32,f32)> = (0..self.nsamples).map( |i| {
-            match i {
-                0 => (0.0, 0.0),
-                _ => (rng.gen_range(-0.5..0.5) * inv_w, rng.gen_range(-0.5..0.5) * inv_h),
-            }
-        }).collect();
+        let samples = samples.start..usize::min(samples.end, self.nsamples);
+        if samples.is_empty() {
+            return;
+        }
+        let mut sampler = self.sampler.build(self.seed, self.nsamples);
+        let aovs: Vec<Aov> = target.aovs.keys().copied().collect();
+        let tile = RenderTarget::new(target.full_width, target.full_height,
+            region.expand(self.filter_margin(), target.buffer.region()), &aovs);
+        let mut taps = Vec::new();
 
-        for y in target.buffer.y_range_iter() {
+        'pixels: for y in region.y..region.y+region.height {
             let v = y as f32 * inv_h;
 
-            for x in target.buffer.x_range_iter() {
+            for x in region.x..region.x+region.width {
                 let u = x as f32 * inv_w;
-                let mut color = Color::black();
+                let mut statistics = SampleStatistics::default();
+                // samples already in the target count towards convergence and the maximum
+                let prior = target.buffer.get_statistics(x, y);
+                let out_of_time = match session.stop_reason() {
+                    Some(StopReason::Cancelled) => break 'pixels,
+                    Some(StopReason::OutOfTime) => true,
+                    None => false,
+                };
 
-                for i in 0..self.nsamples {
-                    let ray = camera.get_ray(u+rands[i].0, v+rands[i].1);
-                    color = color + self.cast(scene, &ray, self.max_depth);
+                for i in samples.clone() {
+                    if out_of_time && prior.count + statistics.count >= 1.0 {
+                        break;
+                    }
+                    if let Some(adaptive) = &self.adaptive {
+                        let taken = prior.merge(&statistics);
+                        if taken.count >= self.nsamples as f32 {
+                            break;
+                        }
+                        if i % adaptive.min_samples.max(1) == 0 && adaptive.converged(&taken) {
+                            break;
+                        }
+                    }
+                    sampler.start_sample(x, y, i);
+                    let jitter = sampler.next_2d();
+                    let (du, dv) = ((jitter.x - 0.5) * inv_w, (jitter.y - 0.5) * inv_h);
+                    let ray = camera.get_ray(u+du, v+dv, sampler.as_mut());
+                    let sample = match &ray {
+                        Some(ray) => self.integrator.radiance(scene, ray, sampler.as_mut()),
+                        None => Color::black(),
+                    };
+                    statistics.push(tonemap::luminance([sample.red, sample.green, sample.blue]));
+
+                    self.filter_taps(&tile.buffer, x, y, jitter.x - 0.5, jitter.y - 0.5, &mut taps);
+                    tile.buffer.splat(sample, &taps);
+
+                    if !tile.aovs.is_empty() {
+                        let hit = ray.as_ref().and_then(|ray| scene.hit(ray, TMIN, TMAX));
+                        for (aov, buffer) in tile.aovs.iter().filter(|(aov, _)| aov.is_filtered()) {
+                            buffer.splat(aov.value(scene, hit.as_ref()), &taps);
+                        }
+                    }
+                    // the other passes take a single ray through the pixel centre
+                    if i == 0 && tile.aovs.keys().any(|aov| !aov.is_filtered()) {
+                        let centre = camera.get_ray(u, v, sampler.as_mut());
+                        let hit = centre.and_then(|ray| scene.hit(&ray, TMIN, TMAX));
+                        for (aov, buffer) in tile.aovs.iter().filter(|(aov, _)| !aov.is_filtered()) {
+                            buffer.add_radiance(x, y, aov.value(scene, hit.as_ref()), 1.0);
+                        }
+                    }
                 }
 
-                // scale and gamma correction
-                let color = Coloru8 {
-                    red:   GAMMA_LUT[(scale*color.red)   as usize],
-                    green: GAMMA_LUT[(scale*color.green) as usize],
-                    blue:  GAMMA_LUT[(scale*color.blue)  as usize],
-                };
-                target.buffer.set_pixel_color_u8(x, y, color);
+                tile.buffer.add_statistics(x, y, &statistics);
+                session.add_samples(statistics.count as u64);
             }
         }
-    }
 
-    fn cast(&self, scene:&Scene, ray:&Ray, depth:usize) -> Color {
-        if depth == 0 {
-            return self.on_miss(ray);
-        }
-        match scene.hit(ray, self.tmin, self.tmax) {
-            None => self.on_miss(ray),
-            Some(hit) => self.on_hit(scene, ray, depth, hit),
+        target.accumulate(&tile);
+        if let Some(buffer) = target.aovs.get(&Aov::SampleCount) {
+            for y in region.y..region.y+region.height {
+                for x in region.x..region.x+region.width {
+                    let n = target.buffer.get_statistics(x, y).count;
+                    buffer.set_radiance(x, y, Color::new(n, n, n));
+                }
+            }
         }
     }
 
-    fn on_miss(&self, ray:&Ray) -> Color {
-        let dir = ray.direction().normalize();
-        let t = 0.5 * (dir.y + 1.0);
-        Color::lerp(t,
-            Color{red:1.0, green:1.0, blue:1.0},
-            Color{red:0.5, green:0.7, blue:1.0},
-        )
-    }
-    
-    fn on_hit(&self, scene:&Scene, ray:&Ray, depth:usize, hit:HitRecord) -> Color {
-        match hit.material.scatter(ray, &hit) {
-            None => Color::black(),
-            Some(scatter) => {
-                scatter.attenuation * self.cast(scene, &scatter.ray, depth-1)
+    /// the pixels of `image` that a sample of pixel (`x`, `y`), offset by
+    /// (`dx`, `dy`) from its centre, lands in and their filter weights
+    fn filter_taps(&self, image:&Image, x:usize, y:usize, dx:f32, dy:f32, taps:&mut Vec<(usize, usize, f32)>) {
+        let radius = self.filter.radius();
+        // pixel offsets in (d - radius, d + radius], so a box of radius 0.5 only
+        // ever covers the sample's own pixel. Working with offsets rather than
+        // image coordinates keeps rounding from moving the sample.
+        let offsets = |d:f32| (d - radius).floor() as isize + 1..=(d + radius).floor() as isize;
+        taps.clear();
+        for oy in offsets(dy) {
+            let Some(ty) = y.checked_add_signed(oy).filter(|ty| image.y_range_iter().contains(ty)) else {
+                continue;
+            };
+            for ox in offsets(dx) {
+                let Some(tx) = x.checked_add_signed(ox).filter(|tx| image.x_range_iter().contains(tx)) else {
+                    continue;
+                };
+                let weight = self.filter.evaluate(ox as f32 - dx, oy as f32 - dy);
+                if weight != 0.0 {
+                    taps.push((tx, ty, weight));
+                }
             }
         }
     }
@@ -0,0 +1,552 @@
+use std::f32::consts::PI;
+use std::f32::consts::FRAC_PI_4;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+type Vector2 = cgmath::Vector2<f32>;
+type Vector3 = cgmath::Vector3<f32>;
+
+/// Source of the numbers a camera sample is built from. The renderer
+/// starts every sample of every pixel, after which each request for a
+/// value is a new dimension: the pixel jitter, the lens, then whatever
+/// the integrator and materials draw along the path. Values for the same
+/// pixel, sample and dimension are the same on every run.
+pub trait Sampler: Send {
+    /// starts drawing the values of sample `index` of pixel (x, y)
+    fn start_sample(&mut self, x:usize, y:usize, index:usize);
+
+    /// uniform in [0, 1)
+    fn next_1d(&mut self) -> f32;
+
+    /// uniform in [0, 1)², the two values are well spread as a pair
+    fn next_2d(&mut self) -> Vector2;
+}
+
+/// Which sampler the renderer draws from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerType {
+    /// white noise, every value independent of the others
+    Independent,
+    /// jittered strata, a grid for pairs, shuffled per pixel and dimension
+    Stratified,
+    /// Halton sequence with a random shift per pixel and dimension
+    Halton,
+    /// Sobol sequence with hashed Owen scrambling per pixel and dimension
+    #[default]
+    Sobol,
+    /// one scrambled Sobol sequence for the whole image, shifted per pixel
+    /// by a blue noise mask so the error left is spread as blue noise
+    BlueNoise,
+}
+
+impl SamplerType {
+    pub fn from_name(name:&str) -> Option<SamplerType> {
+        match name {
+            "independent" => Some(SamplerType::Independent),
+            "stratified" => Some(SamplerType::Stratified),
+            "halton" => Some(SamplerType::Halton),
+            "sobol" => Some(SamplerType::Sobol),
+            "blue_noise" => Some(SamplerType::BlueNoise),
+            _ => None,
+        }
+    }
+
+    /// a sampler for pixels taking up to `nsamples` samples, rendering
+    /// more than that is fine but may not stratify as well
+    pub fn build(&self, seed:u64, nsamples:usize) -> Box<dyn Sampler> {
+        let state = SampleState::new(seed);
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler { state }),
+            SamplerType::Stratified => Box::new(StratifiedSampler::new(seed, nsamples)),
+            SamplerType::Halton => Box::new(HaltonSampler { state }),
+            SamplerType::Sobol => Box::new(SobolSampler { state }),
+            SamplerType::BlueNoise => Box::new(BlueNoiseSampler { state, mask: blue_noise_mask() }),
+        }
+    }
+}
+
+/// where a sampler is: which pixel and sample, and how many values it drew
+#[derive(Clone, Debug)]
+struct SampleState {
+    seed: u64,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed:u64) -> SampleState {
+        SampleState { seed, x: 0, y: 0, index: 0, dimension: 0 }
+    }
+
+    fn start(&mut self, x:usize, y:usize, index:usize) {
+        self.x = x as u32;
+        self.y = y as u32;
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    /// hash of the seed, pixel and current dimension, the same for every sample
+    fn pixel_hash(&self) -> u64 {
+        mix(self.seed.wrapping_add(mix((self.x as u64) | (self.y as u64) << 32)) ^ self.dimension as u64)
+    }
+
+    /// hash of the seed and current dimension, the same for every pixel
+    fn image_hash(&self) -> u64 {
+        mix(self.seed ^ mix(self.dimension as u64))
+    }
+
+    /// white noise for the current dimension of this sample
+    fn random(&self, salt:u64) -> f32 {
+        to_f32(mix(self.pixel_hash() ^ mix((self.index as u64) << 8 | salt)) as u32)
+    }
+}
+
+/// Independent uniform values, each a hash of the seed, pixel, sample and dimension
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl IndependentSampler {
+    pub fn new(seed:u64) -> IndependentSampler {
+        IndependentSampler { state: SampleState::new(seed) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x:usize, y:usize, index:usize) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let u = self.state.random(0);
+        self.state.dimension += 1;
+        u
+    }
+
+    fn next_2d(&mut self) -> Vector2 {
+        let u = Vector2::new(self.state.random(0), self.state.random(1));
+        self.state.dimension += 1;
+        u
+    }
+}
+
+/// Jittered stratification: the samples of a pixel land in different
+/// strata, `nsamples` of them for single values and a square grid of at
+/// least `nsamples` cells for pairs. Which sample gets which stratum is
+/// shuffled per pixel and dimension so dimensions don't line up.
+pub struct StratifiedSampler {
+    state: SampleState,
+    strata: u32,
+    grid: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed:u64, nsamples:usize) -> StratifiedSampler {
+        let strata = nsamples.clamp(1, u32::MAX as usize) as u32;
+        let grid = (strata as f64).sqrt().ceil() as u32;
+        StratifiedSampler { state: SampleState::new(seed), strata, grid }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x:usize, y:usize, index:usize) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let n = self.strata;
+        let stratum = permute(self.state.index % n, n, self.state.pixel_hash() as u32);
+        let u = (stratum as f32 + self.state.random(0)) / n as f32;
+        self.state.dimension += 1;
+        u.min(ONE_MINUS_EPSILON)
+    }
+
+    fn next_2d(&mut self) -> Vector2 {
+        let m = self.grid;
+        let cell = permute(self.state.index % (m * m), m * m, self.state.pixel_hash() as u32);
+        let u = Vector2::new(
+            ((cell % m) as f32 + self.state.random(0)) / m as f32,
+            ((cell / m) as f32 + self.state.random(1)) / m as f32,
+        );
+        self.state.dimension += 1;
+        Vector2::new(u.x.min(ONE_MINUS_EPSILON), u.y.min(ONE_MINUS_EPSILON))
+    }
+}
+
+/// Halton sequence, a prime base per value. Every pixel walks the same
+/// sequence shifted by its own random offset in each dimension. Past the
+/// primes in the table the values are independent.
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed:u64) -> HaltonSampler {
+        HaltonSampler { state: SampleState::new(seed) }
+    }
+
+    fn value(&self, axis:usize) -> f32 {
+        let base = match PRIMES.get(2 * self.state.dimension as usize + axis) {
+            Some(&base) => base,
+            None => return self.state.random(axis as u64),
+        };
+        // Cranley-Patterson rotation, a toroidal shift per pixel
+        let shift = to_f32(mix(self.state.pixel_hash() ^ axis as u64) as u32);
+        let u = radical_inverse(self.state.index, base) + shift;
+        (u - u.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x:usize, y:usize, index:usize) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let u = self.value(0);
+        self.state.dimension += 1;
+        u
+    }
+
+    fn next_2d(&mut self) -> Vector2 {
+        let u = Vector2::new(self.value(0), self.value(1));
+        self.state.dimension += 1;
+        u
+    }
+}
+
+/// Owen scrambled Sobol points after Burley 2020. Every request is the
+/// first two Sobol dimensions with the sample index shuffled and the
+/// values scrambled by hashes of the pixel and dimension, which keeps
+/// each pair well stratified without correlating it to the others.
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed:u64) -> SobolSampler {
+        SobolSampler { state: SampleState::new(seed) }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x:usize, y:usize, index:usize) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let [u] = scrambled_sobol(self.state.index, self.state.pixel_hash());
+        self.state.dimension += 1;
+        u
+    }
+
+    fn next_2d(&mut self) -> Vector2 {
+        let [x, y] = scrambled_sobol(self.state.index, self.state.pixel_hash());
+        self.state.dimension += 1;
+        Vector2::new(x, y)
+    }
+}
+
+/// Blue noise dithered sampling after Georgiev and Fajardo 2016: all
+/// pixels share one scrambled Sobol sequence, shifted per pixel by the
+/// values of a blue noise mask. Neighbouring pixels get very different
+/// shifts so at low sample counts the error looks like fine grain
+/// rather than blotches, which also suits denoising.
+pub struct BlueNoiseSampler {
+    state: SampleState,
+    mask: &'static [f32],
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed:u64) -> BlueNoiseSampler {
+        BlueNoiseSampler { state: SampleState::new(seed), mask: blue_noise_mask() }
+    }
+
+    fn value(&self, u:f32, axis:u64) -> f32 {
+        // each dimension and axis reads the mask at its own toroidal offset
+        let offset = mix(self.state.image_hash() ^ axis);
+        let mx = (self.state.x as usize + offset as usize) % MASK_SIZE;
+        let my = (self.state.y as usize + (offset >> 32) as usize) % MASK_SIZE;
+        let u = u + self.mask[mx + my * MASK_SIZE];
+        (u - u.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, x:usize, y:usize, index:usize) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let [x] = scrambled_sobol(self.state.index, self.state.image_hash());
+        let u = self.value(x, 0);
+        self.state.dimension += 1;
+        u
+    }
+
+    fn next_2d(&mut self) -> Vector2 {
+        let [x, y] = scrambled_sobol(self.state.index, self.state.image_hash());
+        let u = Vector2::new(self.value(x, 0), self.value(y, 1));
+        self.state.dimension += 1;
+        u
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// finalizer of splitmix64, every input bit affects every output bit
+#[inline]
+fn mix(mut z:u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// the top 24 bits fill the mantissa exactly
+#[inline]
+fn to_f32(bits:u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+/// element `i` of a random permutation of 0..n chosen by `seed`, from
+/// Kensler's correlated multi-jittered sampling
+fn permute(mut i:u32, n:u32, seed:u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    // cycle walking, values past n are mixed again until they land in range
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            return ((i as u64 + seed as u64) % n as u64) as u32;
+        }
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+/// digits of `index` in `base` mirrored around the decimal point
+fn radical_inverse(mut index:u32, base:u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut reversed = 0.0;
+    while index > 0 {
+        reversed += (index % base) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    reversed as f32
+}
+
+/// the second Sobol dimension, from the primitive polynomial x + 1, as
+/// tables of the generator matrix times every byte of the index
+const SOBOL_BYTES: [[u32; 256]; 4] = sobol_tables();
+
+const fn sobol_tables() -> [[u32; 256]; 4] {
+    let mut directions = [0u32; 32];
+    let mut v = 1u32 << 31;
+    let mut bit = 0;
+    while bit < 32 {
+        directions[bit] = v;
+        v ^= v >> 1;
+        bit += 1;
+    }
+    let mut tables = [[0u32; 256]; 4];
+    let mut byte = 0;
+    while byte < 4 {
+        let mut value = 0;
+        while value < 256 {
+            let mut x = 0;
+            let mut bit = 0;
+            while bit < 8 {
+                if value & (1 << bit) != 0 {
+                    x ^= directions[8 * byte + bit];
+                }
+                bit += 1;
+            }
+            tables[byte][value] = x;
+            value += 1;
+        }
+        byte += 1;
+    }
+    tables
+}
+
+/// dimension 0 is the van der Corput sequence, dimension 1 uses the tables
+#[inline]
+fn sobol(index:u32, dimension:usize) -> u32 {
+    match dimension {
+        0 => index.reverse_bits(),
+        _ => {
+            let [b0, b1, b2, b3] = index.to_le_bytes();
+            SOBOL_BYTES[0][b0 as usize] ^ SOBOL_BYTES[1][b1 as usize] ^ SOBOL_BYTES[2][b2 as usize] ^ SOBOL_BYTES[3][b3 as usize]
+        }
+    }
+}
+
+/// Laine and Karras' hash with Burley's constants, changing a bit only
+/// affects the bits above it
+#[inline]
+fn laine_karras_permutation(mut x:u32, seed:u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Owen scrambling, every digit is flipped depending on the digits before it
+#[inline]
+fn nested_uniform_scramble(x:u32, seed:u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// the first `N` Sobol dimensions of a shuffled index, each Owen scrambled
+fn scrambled_sobol<const N: usize>(index:u32, hash:u64) -> [f32; N] {
+    let index = nested_uniform_scramble(index, hash as u32);
+    let mut values = [0.0; N];
+    for (dimension, value) in values.iter_mut().enumerate() {
+        let seed = mix(hash ^ (dimension as u64 + 1)) as u32;
+        *value = to_f32(nested_uniform_scramble(sobol(index, dimension), seed));
+    }
+    values
+}
+
+/// edge length of the tiling blue noise mask
+const MASK_SIZE: usize = 64;
+
+/// a tiling mask of MASK_SIZE² values, each rank in [0, 1) used once,
+/// made with Ulichney's void and cluster method the first time it's needed
+fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)
+}
+
+fn void_and_cluster() -> Vec<f32> {
+    const N: usize = MASK_SIZE * MASK_SIZE;
+    const SIGMA: f32 = 1.5;
+
+    // gaussian energy each set pixel spreads over its toroidal neighbourhood
+    let kernel: Vec<f32> = (0..N).map( |i| {
+        let wrap = |d:usize| d.min(MASK_SIZE - d) as f32;
+        let (dx, dy) = (wrap(i % MASK_SIZE), wrap(i / MASK_SIZE));
+        (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+    }).collect();
+    let splat = |energy:&mut [f32], p:usize, sign:f32| {
+        let (px, py) = (p % MASK_SIZE, p / MASK_SIZE);
+        for (q, e) in energy.iter_mut().enumerate() {
+            let dx = (q % MASK_SIZE + MASK_SIZE - px) % MASK_SIZE;
+            let dy = (q / MASK_SIZE + MASK_SIZE - py) % MASK_SIZE;
+            *e += sign * kernel[dx + dy * MASK_SIZE];
+        }
+    };
+    // tightest cluster is the set pixel with the most energy, largest void the empty one with the least
+    let tightest_cluster = |energy:&[f32], set:&[bool]| {
+        (0..N).filter(|&p| set[p]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+    let largest_void = |energy:&[f32], set:&[bool]| {
+        (0..N).filter(|&p| !set[p]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+
+    // a random tenth of the pixels, then moved from clusters into voids until even
+    let ones = N / 10;
+    let mut set = vec![false; N];
+    let mut energy = vec![0.0; N];
+    let mut placed = 0;
+    let mut i = 0u64;
+    while placed < ones {
+        let p = (mix(i) % N as u64) as usize;
+        if !set[p] {
+            set[p] = true;
+            splat(&mut energy, p, 1.0);
+            placed += 1;
+        }
+        i += 1;
+    }
+    // bounded in case a pixel keeps swapping between two voids
+    for _ in 0..N {
+        let cluster = tightest_cluster(&energy, &set);
+        set[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest_void(&energy, &set);
+        set[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; N];
+    // the prototype's pixels are ranked by taking clusters away
+    let (mut prototype, mut prototype_energy) = (set.clone(), energy.clone());
+    for r in (0..ones).rev() {
+        let cluster = tightest_cluster(&prototype_energy, &prototype);
+        prototype[cluster] = false;
+        splat(&mut prototype_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    // the rest by filling voids
+    for r in ones..N {
+        let void = largest_void(&energy, &set);
+        set[void] = true;
+        splat(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    rank.iter().map(|&r| (r as f32 + 0.5) / N as f32).collect()
+}
+
+/// maps a point of the unit square to a uniformly distributed direction
+#[inline]
+pub fn sample_unit_sphere(u:Vector2) -> Vector3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = f32::max(1.0 - z * z, 0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// maps a point of the unit square to the unit disk with Shirley's
+/// concentric mapping, which keeps strata of the square intact
+#[inline]
+pub fn sample_unit_disk(u:Vector2) -> Vector2 {
+    let (a, b) = (2.0 * u.x - 1.0, 2.0 * u.y - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vector2::new(0.0, 0.0);
+    }
+    let (r, theta) = match a.abs() > b.abs() {
+        true => (a, FRAC_PI_4 * (b / a)),
+        false => (b, 2.0 * FRAC_PI_4 - FRAC_PI_4 * (a / b)),
+    };
+    Vector2::new(r * theta.cos(), r * theta.sin())
+}
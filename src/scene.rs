@@ -1,24 +1,225 @@
+use std::sync::Arc;
+use std::collections::HashMap;
 
 use crate::ray::Ray;
+use crate::hittables::Aabb;
+use crate::hittables::Bvh;
 use crate::hittables::Hittable;
 use crate::hittables::HitRecord;
+use crate::hittables::ShapeSample;
+use crate::materials::Material;
+use crate::environment::Environment;
+use crate::environment::GradientEnvironment;
+
+use cgmath::InnerSpace;
+
+type Vector2 = cgmath::Vector2<f32>;
+type Vector3 = cgmath::Vector3<f32>;
 
 pub struct Scene {
     contents: Vec<Box<dyn Hittable>>,
+    bvh: Option<Bvh>,
+    lights: Vec<Arc<dyn Hittable>>,
+    environment: Box<dyn Environment>,
+    /// material ids in the order materials were first pushed, keyed by address
+    materials: HashMap<usize, usize>,
+}
+
+/// Something `Scene::sample_light` can pick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightId {
+    /// index into `Scene::lights`
+    Shape(usize),
+    Environment,
+}
+
+pub struct LightSample {
+    pub light: LightId,
+    /// unit direction from the shading point towards the light
+    pub direction: Vector3,
+    /// distance to the sampled point, infinite for the environment
+    pub distance: f32,
+    /// solid angle pdf, including the probability of choosing the light
+    pub pdf: f32,
+}
+
+/// Stands in for an emissive hittable in the scene's geometry
+/// and tags its hits with the index of the light
+struct LightInstance {
+    index: usize,
+    shape: Arc<dyn Hittable>,
+}
+
+impl Hittable for LightInstance {
+    fn hit(&self, ray:&Ray, tmin:f32, tmax:f32) -> Option<HitRecord> {
+        let mut hit = self.shape.hit(ray, tmin, tmax)?;
+        hit.light = Some(self.index);
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.shape.bounding_box()
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        self.shape.material()
+    }
+
+    fn sample(&self, origin:Vector3, u:Vector2) -> Option<ShapeSample> {
+        self.shape.sample(origin, u)
+    }
+
+    fn pdf(&self, origin:Vector3, hit:&HitRecord) -> f32 {
+        self.shape.pdf(origin, hit)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
 }
 
 impl Scene {
 
     pub fn new() -> Self {
-        Scene { contents: Vec::new() }
+        Scene {
+            contents: Vec::new(),
+            bvh: None,
+            lights: Vec::new(),
+            environment: GradientEnvironment::sky(),
+            materials: HashMap::new(),
+        }
     }
 
+    /// closest hit, tagged with the index of the object that was hit
     pub fn hit(&self, r:&Ray, tmin:f32, tmax:f32) -> Option<HitRecord> {
-        self.contents.hit(r, tmin, tmax)
+        let (mut result, built) = match &self.bvh {
+            None => (None, 0),
+            Some(bvh) => (bvh.hit_object(r, tmin, tmax), bvh.len()),
+        };
+        // anything pushed since the last build is tested linearly
+        let mut closest = result.as_ref().map_or(tmax, |(_, hit)| hit.t);
+        for (i, hittable) in self.contents.iter().enumerate() {
+            if let Some(hit) = hittable.hit(r, tmin, closest) {
+                closest = hit.t;
+                result = Some((built + i, hit));
+            }
+        }
+        result.map( |(object, mut hit)| {
+            hit.object = Some(object);
+            hit
+        })
     }
 
+    /// emissive hittables are also registered as lights
     pub fn push(&mut self, hittable:Box<dyn Hittable>) {
-        self.contents.push(hittable);
+        if let Some(material) = hittable.material() {
+            let next = self.materials.len();
+            self.materials.entry(material_key(material)).or_insert(next);
+        }
+        if !hittable.is_emissive() {
+            self.contents.push(hittable);
+            return;
+        }
+        let shape: Arc<dyn Hittable> = Arc::from(hittable);
+        let index = self.lights.len();
+        self.lights.push(shape.clone());
+        self.contents.push(Box::new(LightInstance { index, shape }));
+    }
+
+    /// builds the acceleration structure over everything pushed so far,
+    /// call once the scene is populated and before rendering
+    pub fn build_bvh(&mut self) {
+        let mut objects = match self.bvh.take() {
+            None => Vec::new(),
+            Some(bvh) => bvh.into_objects(),
+        };
+        objects.append(&mut self.contents);
+        self.bvh = Some(Bvh::new(objects));
+    }
+
+    pub fn lights(&self) -> &[Arc<dyn Hittable>] {
+        &self.lights
+    }
+
+    /// index of `material` in the order materials were first pushed with an
+    /// object, None for materials of hittables that don't report one
+    pub fn material_id(&self, material:&Arc<dyn Material>) -> Option<usize> {
+        self.materials.get(&material_key(material)).copied()
+    }
+
+    /// what rays leaving the scene see, a white to blue sky unless set
+    pub fn set_environment(&mut self, environment:Box<dyn Environment>) {
+        self.environment = environment;
     }
 
+    pub fn environment(&self) -> &dyn Environment {
+        self.environment.as_ref()
+    }
+
+    /// the shape lights plus the environment if it can be sampled
+    fn num_lights(&self) -> usize {
+        self.lights.len() + self.environment.is_sampled() as usize
+    }
+
+    /// picks one light uniformly with `select` and samples a direction
+    /// towards it, the returned pdf includes the probability of choosing the light
+    pub fn sample_light(&self, origin:Vector3, select:f32, u:Vector2) -> Option<LightSample> {
+        let n = self.num_lights();
+        if n == 0 {
+            return None;
+        }
+        let index = usize::min((select * n as f32) as usize, n-1);
+        if index == self.lights.len() {
+            let (direction, pdf) = self.environment.sample(u)?;
+            return Some(LightSample {
+                light: LightId::Environment,
+                direction,
+                distance: f32::INFINITY,
+                pdf: pdf / n as f32,
+            });
+        }
+        let sample = self.lights[index].sample(origin, u)?;
+        let offset = sample.point - origin;
+        let distance = offset.magnitude();
+        Some(LightSample {
+            light: LightId::Shape(index),
+            direction: offset / distance,
+            distance,
+            pdf: sample.pdf / n as f32,
+        })
+    }
+
+    /// pdf of `sample_light` choosing the point of `hit` on light `index`
+    pub fn light_pdf(&self, index:usize, origin:Vector3, hit:&HitRecord) -> f32 {
+        self.lights[index].pdf(origin, hit) / self.num_lights() as f32
+    }
+
+    /// pdf of `sample_light` choosing `direction` towards the environment
+    pub fn environment_pdf(&self, direction:Vector3) -> f32 {
+        match self.environment.is_sampled() {
+            true => self.environment.pdf(direction) / self.num_lights() as f32,
+            false => 0.0,
+        }
+    }
+
+    /// true if nothing blocks the segment between the two points
+    pub fn visible(&self, from:Vector3, to:Vector3, epsilon:f32) -> bool {
+        let offset = to - from;
+        let dist = offset.magnitude();
+        let ray = Ray::new(from, offset);
+        self.hit(&ray, epsilon, dist - epsilon).is_none()
+    }
+
+}
+
+/// materials are told apart by the address they are shared from
+#[inline]
+fn material_key(material:&Arc<dyn Material>) -> usize {
+    Arc::as_ptr(material) as *const () as usize
 }
@@ -0,0 +1,173 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Asks a render to stop, clones share the same flag so one can be
+/// handed to the render and the other kept by whoever may cancel it
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Why a render stopped before taking all of its samples
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// the cancel token was triggered, pixels not reached yet are left empty
+    Cancelled,
+    /// the time budget ran out, pixels not reached yet still get one sample
+    OutOfTime,
+}
+
+/// How far a render has come, handed to the progress callback
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    /// camera samples taken so far over the whole image
+    pub samples: u64,
+    pub elapsed: Duration,
+    /// estimated time left, None until the first tile is done
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    /// share of the tiles done, between 0 and 1
+    pub fn fraction(&self) -> f32 {
+        match self.tiles_total {
+            0 => 1.0,
+            total => self.tiles_done as f32 / total as f32,
+        }
+    }
+}
+
+type ProgressCallback = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// Shared state of one render: reports progress as tiles complete and
+/// tells the renderer when to stop, either because it was cancelled or
+/// because its time budget ran out. Renderers check it once per pixel.
+pub struct RenderSession {
+    cancel: CancelToken,
+    budget: Option<Duration>,
+    on_progress: Option<ProgressCallback>,
+    created: Instant,
+    /// nanoseconds from `created` to the start of the render, atomic
+    /// since every worker reads the clock once per pixel
+    started: AtomicU64,
+    tiles_total: AtomicUsize,
+    tiles_done: AtomicUsize,
+    samples: AtomicU64,
+}
+
+impl Default for RenderSession {
+    fn default() -> Self {
+        RenderSession {
+            cancel: CancelToken::new(),
+            budget: None,
+            on_progress: None,
+            created: Instant::now(),
+            started: AtomicU64::new(0),
+            tiles_total: AtomicUsize::new(0),
+            tiles_done: AtomicUsize::new(0),
+            samples: AtomicU64::new(0),
+        }
+    }
+}
+
+impl RenderSession {
+    pub fn new() -> RenderSession {
+        RenderSession::default()
+    }
+
+    /// stops the render once `cancel`, or any of its clones, is cancelled
+    pub fn with_cancel_token(mut self, cancel:CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// stops sampling `budget` after the render begins
+    pub fn with_time_budget(mut self, budget:Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// called every time a tile is done
+    pub fn with_progress<F>(mut self, on_progress:F) -> Self
+    where F: Fn(&Progress) + Send + Sync + 'static
+    {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// starts the clock and the count of `tiles_total` tiles still to render
+    pub fn begin(&self, tiles_total:usize) {
+        self.started.store(self.created.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.tiles_total.store(tiles_total, Ordering::Relaxed);
+        self.tiles_done.store(0, Ordering::Relaxed);
+        self.samples.store(0, Ordering::Relaxed);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        let started = Duration::from_nanos(self.started.load(Ordering::Relaxed));
+        self.created.elapsed().saturating_sub(started)
+    }
+
+    /// None while the render should go on
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        if self.cancel.is_cancelled() {
+            return Some(StopReason::Cancelled);
+        }
+        match self.budget {
+            Some(budget) if self.elapsed() >= budget => Some(StopReason::OutOfTime),
+            _ => None,
+        }
+    }
+
+    pub fn add_samples(&self, samples:u64) {
+        self.samples.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    /// counts a finished tile and reports progress
+    pub fn tile_done(&self) {
+        self.tiles_done.fetch_add(1, Ordering::Relaxed);
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(&self.progress());
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let tiles_done = self.tiles_done.load(Ordering::Relaxed);
+        let tiles_total = self.tiles_total.load(Ordering::Relaxed);
+        let elapsed = self.elapsed();
+        let eta = match tiles_done {
+            0 => None,
+            done => Some(elapsed.mul_f64(tiles_total.saturating_sub(done) as f64 / done as f64)),
+        };
+        Progress {
+            tiles_done,
+            tiles_total,
+            samples: self.samples.load(Ordering::Relaxed),
+            elapsed,
+            eta,
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::f32::consts::PI;
+
+use crate::image::Color;
+use crate::environment::Environment;
+use crate::hittables::orthonormal_basis;
+use crate::tonemap::luminance;
+
+use cgmath::InnerSpace;
+
+type Vector2 = cgmath::Vector2<f32>;
+type Vector3 = cgmath::Vector3<f32>;
+
+/// scene units per kcd/m², puts a clear zenith somewhere around 0.5
+const SKY_SCALE: f32 = 0.05;
+/// radiance of the sun above the atmosphere in the same units
+const SUN_RADIANCE: f32 = 1.0e5;
+/// wavelengths in micrometers standing in for the red, green and blue channels
+const WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+
+#[derive(Clone, Copy)]
+pub struct SkyParameters {
+    /// towards the sun, doesn't need to be normalized
+    pub sun_direction: Vector3,
+    /// haziness, 2 is a very clear sky and 10 is hazy, clamped to that range
+    pub turbidity: f32,
+    /// reflectance of the ground below the horizon
+    pub ground_albedo: Color,
+    /// in degrees, the sun is left out when 0
+    pub sun_angular_diameter: f32,
+    /// scales the sky and the sun together
+    pub intensity: f32,
+}
+
+impl Default for SkyParameters {
+    fn default() -> Self {
+        SkyParameters {
+            sun_direction: Vector3::new(0.0, 1.0, 1.0),
+            turbidity: 3.0,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            sun_angular_diameter: 0.53,
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Perez luminance distribution, A to E from the Preetham paper
+#[derive(Clone, Copy)]
+struct Perez([f32; 5]);
+
+impl Perez {
+    /// relative value at angle `theta` from the zenith and `gamma` from the sun
+    #[inline]
+    fn eval(&self, cos_theta:f32, gamma:f32, cos_gamma:f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        (1.0 + a * (b / cos_theta.max(1e-3)).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Directional light with the size of a disk in the sky
+#[derive(Clone, Copy)]
+pub struct Sun {
+    /// unit direction towards the sun
+    pub direction: Vector3,
+    pub radiance: Color,
+    cos_max: f32,
+    /// 1 - cos_max, kept separately as the disk is tiny
+    one_minus_cos_max: f32,
+}
+
+impl Sun {
+    /// `angular_diameter` is in degrees
+    pub fn new(direction:Vector3, angular_diameter:f32, radiance:Color) -> Sun {
+        let half_angle = 0.5 * angular_diameter.to_radians();
+        let one_minus_cos_max = 2.0 * (0.5 * half_angle).sin().powi(2);
+        Sun {
+            direction: direction.normalize(),
+            radiance,
+            cos_max: half_angle.cos(),
+            one_minus_cos_max,
+        }
+    }
+
+    pub fn solid_angle(&self) -> f32 {
+        2.0 * PI * self.one_minus_cos_max
+    }
+
+    #[inline]
+    pub fn contains(&self, direction:Vector3) -> bool {
+        cgmath::dot(direction, self.direction) >= self.cos_max
+    }
+
+    /// uniform over the disk
+    pub fn sample(&self, u:Vector2) -> Vector3 {
+        let one_minus_cos = u.x * self.one_minus_cos_max;
+        let cos_theta = 1.0 - one_minus_cos;
+        let sin_theta = f32::max(0.0, one_minus_cos * (2.0 - one_minus_cos)).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let (s, t) = orthonormal_basis(self.direction);
+        sin_theta * phi.cos() * s + sin_theta * phi.sin() * t + cos_theta * self.direction
+    }
+
+    pub fn pdf(&self) -> f32 {
+        1.0 / self.solid_angle()
+    }
+}
+
+/// Preetham analytic daylight with a sun disk and a lambertian ground
+/// lit by both. The sun is sampled as a light, the rest of the sphere
+/// uniformly.
+pub struct SkyEnvironment {
+    sun_direction: Vector3,
+    cos_theta_sun: f32,
+    theta_sun: f32,
+    zenith: [f32; 3],
+    perez: [Perez; 3],
+    intensity: f32,
+    ground: Color,
+    sun: Option<Sun>,
+    /// chance of sampling the sun rather than the sphere
+    sun_probability: f32,
+}
+
+impl SkyEnvironment {
+    pub fn new(params:SkyParameters) -> Box<SkyEnvironment> {
+        let t = params.turbidity.clamp(2.0, 10.0);
+        let sun_direction = params.sun_direction.normalize();
+        // the model only covers suns above the horizon
+        let theta_sun = f32::acos(sun_direction.y.clamp(0.0, 1.0));
+
+        let perez = [
+            Perez([0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251, 0.1206*t - 2.5771, -0.0670*t + 0.3703]),
+            Perez([-0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452]),
+            Perez([-0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529]),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = f32::max(0.0, (4.0453*t - 4.9710) * chi.tan() - 0.2155*t + 2.4192);
+        let th = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+        let poly = |c:[f32; 4]| c[0]*th[0] + c[1]*th[1] + c[2]*th[2] + c[3]*th[3];
+        let zenith_x = t*t * poly([0.00166, -0.00375, 0.00209, 0.0])
+            + t * poly([-0.02903, 0.06377, -0.03202, 0.00394])
+            + poly([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t*t * poly([0.00275, -0.00610, 0.00317, 0.0])
+            + t * poly([-0.04214, 0.08970, -0.04153, 0.00516])
+            + poly([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let mut sky = SkyEnvironment {
+            sun_direction,
+            cos_theta_sun: theta_sun.cos(),
+            theta_sun,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+            intensity: params.intensity,
+            ground: Color::black(),
+            sun: None,
+            sun_probability: 0.0,
+        };
+
+        let sun = match params.sun_angular_diameter > 0.0 && sun_direction.y > 0.0 {
+            true => {
+                let transmittance = sun_transmittance(t, sun_direction.y);
+                let [r, g, b] = transmittance.map(|tau| params.intensity * SUN_RADIANCE * tau);
+                Some(Sun::new(sun_direction, params.sun_angular_diameter, Color::new(r, g, b)))
+            }
+            false => None,
+        };
+
+        // light reaching the ground from the sky and the sun
+        let sky_irradiance = sky.sky_irradiance();
+        let sun_irradiance = match &sun {
+            Some(sun) => (sun.solid_angle() * sun_direction.y) * sun.radiance,
+            None => Color::black(),
+        };
+        sky.ground = (1.0 / PI) * (params.ground_albedo * (sky_irradiance + sun_irradiance));
+
+        // pick the sun about as often as it contributes
+        if let Some(sun) = &sun {
+            let c = sun.radiance;
+            let sun_power = luminance([c.red, c.green, c.blue]) * sun.solid_angle();
+            let s = sky_irradiance;
+            let g = sky.ground;
+            let sky_power = luminance([s.red, s.green, s.blue]) + 2.0 * PI * luminance([g.red, g.green, g.blue]);
+            sky.sun_probability = (sun_power / (sun_power + sky_power)).clamp(0.1, 0.9);
+        }
+        sky.sun = sun;
+        Box::new(sky)
+    }
+
+    pub fn sun(&self) -> Option<&Sun> {
+        self.sun.as_ref()
+    }
+
+    /// Preetham sky radiance above the horizon, without the sun disk
+    fn sky_radiance(&self, direction:Vector3) -> Color {
+        let cos_theta = direction.y.max(0.0);
+        let cos_gamma = cgmath::dot(direction, self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+
+        let [luminance, x, y] = [0, 1, 2].map( |i| {
+            let zenith = self.perez[i].eval(1.0, self.theta_sun, self.cos_theta_sun);
+            self.zenith[i] * self.perez[i].eval(cos_theta, gamma, cos_gamma) / zenith
+        });
+        if y <= 0.0 {
+            return Color::black();
+        }
+
+        // xyY to XYZ to linear sRGB
+        let luminance = SKY_SCALE * self.intensity * luminance;
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        Color::new(
+            f32::max(0.0, 3.2406*big_x - 1.5372*luminance - 0.4986*big_z),
+            f32::max(0.0, -0.9689*big_x + 1.8758*luminance + 0.0415*big_z),
+            f32::max(0.0, 0.0557*big_x - 0.2040*luminance + 1.0570*big_z),
+        )
+    }
+
+    /// cosine weighted integral of the sky over the upper hemisphere
+    fn sky_irradiance(&self) -> Color {
+        const THETA_STEPS: usize = 32;
+        const PHI_STEPS: usize = 64;
+        let d_theta = 0.5 * PI / THETA_STEPS as f32;
+        let d_phi = 2.0 * PI / PHI_STEPS as f32;
+        let mut irradiance = Color::black();
+        for i in 0..THETA_STEPS {
+            let theta = (i as f32 + 0.5) * d_theta;
+            let weight = theta.cos() * theta.sin() * d_theta * d_phi;
+            for j in 0..PHI_STEPS {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let direction = Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                irradiance = irradiance + weight * self.sky_radiance(direction);
+            }
+        }
+        irradiance
+    }
+}
+
+/// fraction of the sunlight making it through the atmosphere to
+/// the ground per channel, rayleigh and aerosol scattering only
+fn sun_transmittance(turbidity:f32, cos_theta:f32) -> [f32; 3] {
+    let theta = cos_theta.clamp(0.0, 1.0).acos().to_degrees();
+    let air_mass = 1.0 / (cos_theta + 0.15 * (93.885 - theta).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    WAVELENGTHS.map( |lambda| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+        rayleigh * aerosol
+    })
+}
+
+const UNIFORM_SPHERE_PDF: f32 = 1.0 / (4.0 * PI);
+
+impl Environment for SkyEnvironment {
+    fn radiance(&self, direction:Vector3) -> Color {
+        if direction.y < 0.0 {
+            return self.ground;
+        }
+        match &self.sun {
+            Some(sun) if sun.contains(direction) => sun.radiance + self.sky_radiance(direction),
+            _ => self.sky_radiance(direction),
+        }
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, u:Vector2) -> Option<(Vector3, f32)> {
+        let direction = match &self.sun {
+            Some(sun) if u.x < self.sun_probability => {
+                sun.sample(Vector2::new(u.x / self.sun_probability, u.y))
+            }
+            _ => {
+                let ux = match self.sun {
+                    Some(_) => (u.x - self.sun_probability) / (1.0 - self.sun_probability),
+                    None => u.x,
+                };
+                let z = 1.0 - 2.0 * ux;
+                let r = f32::max(0.0, 1.0 - z*z).sqrt();
+                let phi = 2.0 * PI * u.y;
+                Vector3::new(r * phi.cos(), z, r * phi.sin())
+            }
+        };
+        Some((direction, self.pdf(direction)))
+    }
+
+    fn pdf(&self, direction:Vector3) -> f32 {
+        match &self.sun {
+            Some(sun) => {
+                let sun_pdf = match sun.contains(direction) {
+                    true => sun.pdf(),
+                    false => 0.0,
+                };
+                self.sun_probability * sun_pdf + (1.0 - self.sun_probability) * UNIFORM_SPHERE_PDF
+            }
+            None => UNIFORM_SPHERE_PDF,
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::sync::Arc;
+
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use cgmath::InnerSpace;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::image::Color;
+use crate::image::Image;
+
+type Vector2 = cgmath::Vector2<f32>;
+type Vector3 = cgmath::Vector3<f32>;
+
+/// Color that varies over a surface, evaluated at the uv
+/// coordinates and world position of a hit
+pub trait Texture: Sync+Send {
+    fn value(&self, uv:Vector2, point:Vector3) -> Color;
+}
+
+pub struct SolidColor {
+    pub color:Color,
+}
+
+impl SolidColor {
+    pub fn new(color:Color) -> Arc<SolidColor> {
+        Arc::new(SolidColor{color})
+    }
+}
+
+impl Texture for SolidColor {
+    #[inline]
+    fn value(&self, _uv:Vector2, _point:Vector3) -> Color {
+        self.color
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureMapping {
+    /// pattern follows the surface parameterization
+    Uv,
+    /// pattern is fixed in world space, like a solid block of material
+    Point,
+}
+
+pub struct CheckerTexture {
+    pub even:Arc<dyn Texture>,
+    pub odd:Arc<dyn Texture>,
+    /// number of squares per unit in uv space, or size of a cube in world space
+    pub scale:f32,
+    pub mapping:TextureMapping,
+}
+
+impl CheckerTexture {
+    /// world space checker made of `scale` sized cubes
+    pub fn new(scale:f32, even:Color, odd:Color) -> Arc<CheckerTexture> {
+        Arc::new(CheckerTexture{
+            even: SolidColor::new(even),
+            odd: SolidColor::new(odd),
+            scale,
+            mapping: TextureMapping::Point,
+        })
+    }
+
+    /// checker over the uv square with `frequency` squares along each side
+    pub fn new_uv(frequency:f32, even:Color, odd:Color) -> Arc<CheckerTexture> {
+        Arc::new(CheckerTexture{
+            even: SolidColor::new(even),
+            odd: SolidColor::new(odd),
+            scale: frequency,
+            mapping: TextureMapping::Uv,
+        })
+    }
+
+    pub fn new_textured(scale:f32, mapping:TextureMapping, even:Arc<dyn Texture>, odd:Arc<dyn Texture>) -> Arc<CheckerTexture> {
+        Arc::new(CheckerTexture{even, odd, scale, mapping})
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, uv:Vector2, point:Vector3) -> Color {
+        let cells = match self.mapping {
+            TextureMapping::Uv => {
+                (uv.x * self.scale).floor() as i64 + (uv.y * self.scale).floor() as i64
+            }
+            TextureMapping::Point => {
+                let inv = 1.0 / self.scale;
+                (point.x * inv).floor() as i64
+                    + (point.y * inv).floor() as i64
+                    + (point.z * inv).floor() as i64
+            }
+        };
+        match cells % 2 == 0 {
+            true => self.even.value(uv, point),
+            false => self.odd.value(uv, point),
+        }
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+/// Gradient noise on a lattice of random unit vectors
+pub struct Perlin {
+    gradients: Vec<Vector3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed:u64) -> Perlin {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let gradients = (0..PERLIN_POINTS).map( |_| {
+            loop {
+                let v = Vector3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                );
+                let len2 = v.magnitude2();
+                if len2 > 1e-6 && len2 <= 1.0 {
+                    break v / len2.sqrt();
+                }
+            }
+        }).collect();
+
+        let permutation = |rng:&mut SmallRng| {
+            let mut p: Vec<usize> = (0..PERLIN_POINTS).collect();
+            for i in (1..PERLIN_POINTS).rev() {
+                p.swap(i, rng.gen_range(0..=i));
+            }
+            p
+        };
+        let perm_x = permutation(&mut rng);
+        let perm_y = permutation(&mut rng);
+        let perm_z = permutation(&mut rng);
+        Perlin { gradients, perm_x, perm_y, perm_z }
+    }
+
+    /// smooth noise in roughly [-1, 1]
+    pub fn noise(&self, p:Vector3) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // hermite smoothing hides the lattice
+        let (uu, vv, ww) = (u*u*(3.0-2.0*u), v*v*(3.0-2.0*v), w*w*(3.0-2.0*w));
+
+        let mask = (PERLIN_POINTS - 1) as i64;
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[((i + di) & mask) as usize]
+                        ^ self.perm_y[((j + dj) & mask) as usize]
+                        ^ self.perm_z[((k + dk) & mask) as usize];
+                    let (fi, fj, fk) = (di as f32, dj as f32, dk as f32);
+                    let weight = Vector3::new(u - fi, v - fj, w - fk);
+                    accum += (fi*uu + (1.0-fi)*(1.0-uu))
+                        * (fj*vv + (1.0-fj)*(1.0-vv))
+                        * (fk*ww + (1.0-fk)*(1.0-ww))
+                        * cgmath::dot(self.gradients[index], weight);
+                }
+            }
+        }
+        accum
+    }
+
+    /// sum of `depth` octaves of absolute noise, each at twice the
+    /// frequency and half the weight of the last
+    pub fn turbulence(&self, p:Vector3, depth:usize) -> f32 {
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        accum.abs()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseKind {
+    Smooth,
+    Turbulence,
+    /// sine stripes along z displaced by turbulence
+    Marble,
+}
+
+const TURBULENCE_DEPTH: usize = 7;
+
+pub struct NoiseTexture {
+    pub perlin:Perlin,
+    pub color:Color,
+    pub scale:f32,
+    pub kind:NoiseKind,
+}
+
+impl NoiseTexture {
+    pub fn new(kind:NoiseKind, scale:f32, color:Color, seed:u64) -> Arc<NoiseTexture> {
+        Arc::new(NoiseTexture{ perlin: Perlin::new(seed), color, scale, kind })
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _uv:Vector2, point:Vector3) -> Color {
+        let p = self.scale * point;
+        let intensity = match self.kind {
+            NoiseKind::Smooth => 0.5 * (1.0 + self.perlin.noise(p)),
+            NoiseKind::Turbulence => self.perlin.turbulence(p, TURBULENCE_DEPTH),
+            NoiseKind::Marble => {
+                0.5 * (1.0 + (p.z + 10.0 * self.perlin.turbulence(p, TURBULENCE_DEPTH)).sin())
+            }
+        };
+        intensity * self.color
+    }
+}
+
+/// Bilinearly filtered image lookup, uv (0,0) is the bottom left
+/// corner and the texture repeats outside the unit square
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// linear colors, bottom row first
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn new(width:usize, height:usize, pixels:Vec<Color>) -> Arc<ImageTexture> {
+        assert!(width > 0 && height > 0, "image texture can't be empty");
+        assert_eq!(pixels.len(), width*height, "image texture needs width*height pixels");
+        Arc::new(ImageTexture{ width, height, pixels })
+    }
+
+    /// decodes the 8 bit sRGB pixels of the image to linear colors
+    pub fn from_image(image:&Image) -> Arc<ImageTexture> {
+        let width = image.width();
+        let height = image.height();
+        let mut pixels = Vec::with_capacity(width*height);
+        for (x, y) in image {
+            let c = image.get_pixel_color_u8(x, y);
+            pixels.push(Color::new(srgb_to_linear(c.red), srgb_to_linear(c.green), srgb_to_linear(c.blue)));
+        }
+        ImageTexture::new(width, height, pixels)
+    }
+
+    /// takes the linear radiance of the image as it is
+    pub fn from_radiance(image:&Image) -> Arc<ImageTexture> {
+        let pixels = image.into_iter().map(|(x, y)| image.get_radiance(x, y)).collect();
+        ImageTexture::new(image.width(), image.height(), pixels)
+    }
+
+    pub fn load_bmp(path:&str) -> std::io::Result<Arc<ImageTexture>> {
+        let image = Image::read_bmp(path)?;
+        Ok(ImageTexture::from_image(&image))
+    }
+
+    pub fn load_hdr(path:&str) -> std::io::Result<Arc<ImageTexture>> {
+        let image = Image::read_hdr(path)?;
+        Ok(ImageTexture::from_radiance(&image))
+    }
+
+    /// loads a Radiance .hdr as linear data, anything else as an sRGB bitmap
+    pub fn load(path:&str) -> std::io::Result<Arc<ImageTexture>> {
+        let is_hdr = std::path::Path::new(path).extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr"));
+        match is_hdr {
+            true => ImageTexture::load_hdr(path),
+            false => ImageTexture::load_bmp(path),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub fn texel(&self, x:usize, y:usize) -> Color {
+        self.pixels[y*self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv:Vector2, _point:Vector3) -> Color {
+        // texel centers sit at half integer coordinates
+        let x = (uv.x - uv.x.floor()) * self.width as f32 - 0.5;
+        let y = (uv.y - uv.y.floor()) * self.height as f32 - 0.5;
+        let (fx, fy) = (x.floor(), y.floor());
+        let (tx, ty) = (x - fx, y - fy);
+
+        let wrap = |i:f32, n:usize| (i as i64).rem_euclid(n as i64) as usize;
+        let (x0, x1) = (wrap(fx, self.width), wrap(fx + 1.0, self.width));
+        let (y0, y1) = (wrap(fy, self.height), wrap(fy + 1.0, self.height));
+
+        let bottom = Color::lerp(tx, self.texel(x0, y0), self.texel(x1, y0));
+        let top = Color::lerp(tx, self.texel(x0, y1), self.texel(x1, y1));
+        Color::lerp(ty, bottom, top)
+    }
+}
+
+#[inline]
+pub fn srgb_to_linear(c:u8) -> f32 {
+    let c = c as f32 / 255.0;
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
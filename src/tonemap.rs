@@ -0,0 +1,146 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::image::Color;
+use crate::image::Coloru8;
+
+/// Operators compressing scene linear radiance into the displayable [0, 1] range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapping {
+    /// no compression, anything above 1.0 clips
+    #[default]
+    Clamp,
+    /// luminance based Reinhard, keeps hues but never reaches white
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference and output transforms
+    Aces,
+    /// minimal AgX with the default look, desaturates highlights towards white
+    Agx,
+}
+
+impl ToneMapping {
+    pub fn from_name(name:&str) -> Option<ToneMapping> {
+        match name {
+            "clamp" | "none" => Some(ToneMapping::Clamp),
+            "reinhard" => Some(ToneMapping::Reinhard),
+            "aces" => Some(ToneMapping::Aces),
+            "agx" => Some(ToneMapping::Agx),
+            _ => None,
+        }
+    }
+
+    /// maps linear radiance to display linear values in [0, 1]
+    pub fn apply(&self, c:[f32; 3]) -> [f32; 3] {
+        match self {
+            ToneMapping::Clamp => c.map(|v| v.clamp(0.0, 1.0)),
+            ToneMapping::Reinhard => {
+                let l = luminance(c);
+                match l > 0.0 {
+                    true => c.map(|v| (v / (1.0 + l)).clamp(0.0, 1.0)),
+                    false => [0.0; 3],
+                }
+            }
+            ToneMapping::Aces => {
+                let c = mul(&ACES_INPUT, c).map( |v| {
+                    (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081)
+                });
+                mul(&ACES_OUTPUT, c).map(|v| v.clamp(0.0, 1.0))
+            }
+            ToneMapping::Agx => {
+                let c = mul(&AGX_INSET, c).map( |v| {
+                    let ev = v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+                    agx_contrast((ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV))
+                });
+                // the curve's output is display encoded with a 2.2 gamma
+                mul(&AGX_OUTSET, c).map(|v| v.clamp(0.0, 1.0).powf(2.2))
+            }
+        }
+    }
+}
+
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [ 1.60475, -0.53108, -0.07367],
+    [-0.10208,  1.10813, -0.00605],
+    [-0.00327, -0.07276,  1.07602],
+];
+
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.8424791, 0.0784336, 0.07922375],
+    [0.04232824, 0.8784686, 0.07916613],
+    [0.04237565, 0.0784336, 0.879143],
+];
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.196879, -0.09802088, -0.09902974],
+    [-0.05289685, 1.151903, -0.09896118],
+    [-0.05297164, -0.09804345, 1.151074],
+];
+
+/// polynomial fit of the AgX base contrast sigmoid
+#[inline]
+fn agx_contrast(x:f32) -> f32 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+}
+
+#[inline]
+fn mul(m:&[[f32; 3]; 3], c:[f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * c[0] + row[1] * c[1] + row[2] * c[2])
+}
+
+/// Rec. 709 relative luminance
+#[inline]
+pub fn luminance(c:[f32; 3]) -> f32 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+/// sRGB opto-electronic transfer function, the inverse of `textures::srgb_to_linear`
+#[inline]
+pub fn linear_to_srgb(c:f32) -> f32 {
+    match c <= 0.0031308 {
+        true => 12.92 * c,
+        false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+/// Turns scene linear radiance into 8 bit sRGB: exposure, then a tone
+/// operator, then the sRGB encoding
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DisplayTransform {
+    /// in stops, every step doubles the brightness
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+}
+
+impl DisplayTransform {
+    pub fn new(exposure:f32, tone_mapping:ToneMapping) -> DisplayTransform {
+        DisplayTransform { exposure, tone_mapping }
+    }
+
+    /// display encoded color with channels in [0, 1]
+    pub fn apply(&self, color:Color) -> Color {
+        let scale = self.exposure.exp2();
+        let c = [color.red, color.green, color.blue]
+            .map(|v| match v.is_finite() { true => scale * v, false => 0.0 });
+        let [red, green, blue] = self.tone_mapping.apply(c).map(linear_to_srgb);
+        Color::new(red, green, blue)
+    }
+
+    pub fn to_u8(&self, color:Color) -> Coloru8 {
+        let c = self.apply(color);
+        let quantize = |v:f32| (255.0 * v).round() as u8;
+        Coloru8 {
+            red:   quantize(c.red),
+            green: quantize(c.green),
+            blue:  quantize(c.blue),
+        }
+    }
+}